@@ -0,0 +1,111 @@
+//! Benchmarks for the version parsing done while reading the crates.io dump.
+//!
+//! Run with `cargo bench`.
+
+use clippy_lint_test::{dump::add_version, LatestVersions, Version};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use std::collections::HashMap;
+
+/// A representative sample of version numbers from the dump.
+const VERSIONS: &[&str] = &[
+    "0.1.0",
+    "0.1.1",
+    "0.2.0",
+    "1.0.0",
+    "1.0.1",
+    "1.2.3",
+    "0.10.12",
+    "2.0.0-alpha.1",
+    "2.0.0-alpha.2",
+    "2.0.0-beta.1",
+    "2.0.0-rc.1",
+    "1.0.0+build.1",
+    "0.3.0-beta.2+20220101",
+    "10.20.30",
+    "65535.65535.65535",
+];
+
+/// Versions which are slow to handle. Long pre-release chains on the same version and streams
+/// which keep changing.
+fn pathological_versions() -> Vec<String> {
+    let mut versions = Vec::new();
+    for i in 0..200 {
+        versions.push(format!("1.0.0-alpha.{}", i));
+        versions.push(format!("1.0.0-stream{}.{}", i % 20, i));
+    }
+    versions.push("1.0.0-alpha.1.2.3.4.5.6.7.8.9".into());
+    versions.push(format!("1.0.0-{}.1", "x".repeat(1000)));
+    versions.push(format!("1.0.0+{}", "build.".repeat(200)));
+    versions
+}
+
+fn parse(c: &mut Criterion) {
+    c.bench_function("parse", |b| {
+        b.iter(|| {
+            for v in VERSIONS {
+                black_box(Version::parse(black_box(v)));
+            }
+        });
+    });
+
+    let pathological = pathological_versions();
+    c.bench_function("parse pathological", |b| {
+        b.iter(|| {
+            for v in &pathological {
+                black_box(Version::parse(black_box(v)));
+            }
+        });
+    });
+}
+
+fn push(c: &mut Criterion) {
+    let parsed: Vec<_> = VERSIONS.iter().filter_map(|v| Version::parse(v)).collect();
+    c.bench_function("push", |b| {
+        b.iter(|| {
+            let mut latest = LatestVersions::default();
+            for v in &parsed {
+                latest.push(v.clone());
+            }
+            latest
+        });
+    });
+
+    let pathological = pathological_versions();
+    let parsed: Vec<_> = pathological
+        .iter()
+        .filter_map(|v| Version::parse(v))
+        .collect();
+    c.bench_function("push pathological", |b| {
+        b.iter(|| {
+            let mut latest = LatestVersions::default();
+            for v in &parsed {
+                latest.push(v.clone());
+            }
+            latest
+        });
+    });
+}
+
+fn add_versions(c: &mut Criterion) {
+    // Many crates each with a release history, as the versions table is read.
+    let records: Vec<_> = (0..1000)
+        .flat_map(|id| {
+            VERSIONS
+                .iter()
+                .enumerate()
+                .map(move |(i, v)| (id.to_string(), *v, if i % 7 == 0 { "t" } else { "f" }))
+        })
+        .collect();
+    c.bench_function("add_version 1000 crates", |b| {
+        b.iter(|| {
+            let mut versions = HashMap::new();
+            for (id, num, yanked) in &records {
+                add_version(&mut versions, id, num, yanked);
+            }
+            versions
+        });
+    });
+}
+
+criterion_group!(benches, parse, push, add_versions);
+criterion_main!(benches);
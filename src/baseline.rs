@@ -0,0 +1,233 @@
+//! Results read from a previous JSON report.
+
+use crate::SkipReason;
+use anyhow::{Context, Result};
+use serde_json::Value;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, fs,
+    path::Path,
+};
+
+/// The results of a previous run read from a JSON report.
+pub struct Baseline {
+    /// The crates each tested lint fired on.
+    lints: HashMap<String, HashSet<String>>,
+    skipped: HashSet<String>,
+}
+impl Baseline {
+    pub fn read(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("error reading file `{}`", path.display()))?;
+        Self::parse(&contents)
+            .with_context(|| format!("error parsing baseline report `{}`", path.display()))
+    }
+
+    fn parse(contents: &str) -> Result<Self> {
+        let report: Value = serde_json::from_str(contents)?;
+        let summary = &report["summary"];
+        let mut lints: HashMap<String, HashSet<String>> = summary["lints"]
+            .as_object()
+            .context("missing lint counts")?
+            .keys()
+            .map(|lint| (lint.clone(), HashSet::new()))
+            .collect();
+        for krate in report["crates"].as_array().context("missing crate list")? {
+            let name = krate["crate"].as_str().context("missing crate name")?;
+            for warning in krate["warnings"].as_array().into_iter().flatten() {
+                if let Some(crates) = warning["lint"].as_str().and_then(|l| lints.get_mut(l)) {
+                    crates.insert(name.to_owned());
+                }
+            }
+        }
+        let skipped = summary["skipped"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|x| x["crate"].as_str())
+            .map(str::to_owned)
+            .collect();
+        Ok(Self { lints, skipped })
+    }
+
+    /// Finds all lints which fired on more than `max_regression` additional crates compared to the
+    /// baseline. Only crates which were checked by this run and weren't skipped by either run are
+    /// counted. Lints which weren't tested by the baseline are ignored.
+    pub fn regressions(
+        &self,
+        lints: &HashMap<String, HashSet<String>>,
+        checked: &HashSet<String>,
+        skipped: &[(String, SkipReason)],
+        max_regression: usize,
+    ) -> Vec<Regression> {
+        let skipped: HashSet<_> = skipped.iter().map(|(krate, _)| krate.as_str()).collect();
+        let is_counted = |krate: &&String| {
+            checked.contains(*krate)
+                && !skipped.contains(krate.as_str())
+                && !self.skipped.contains(*krate)
+        };
+        let mut res: Vec<_> = lints
+            .iter()
+            .filter_map(|(lint, crates)| {
+                let baseline = self.lints.get(lint)?.iter().filter(is_counted).count();
+                let current = crates.iter().filter(is_counted).count();
+                (current > baseline + max_regression).then(|| Regression {
+                    lint: lint.clone(),
+                    baseline,
+                    current,
+                })
+            })
+            .collect();
+        res.sort_by(|x, y| x.lint.cmp(&y.lint));
+        res
+    }
+}
+
+/// Reads the crates which caused an ICE from a previous JSON report.
+pub fn read_ice_crates(path: &Path) -> Result<Vec<String>> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("error reading file `{}`", path.display()))?;
+    parse_ice_crates(&contents)
+        .with_context(|| format!("error parsing report `{}`", path.display()))
+}
+
+fn parse_ice_crates(contents: &str) -> Result<Vec<String>> {
+    let report: Value = serde_json::from_str(contents)?;
+    let mut crates = Vec::new();
+    for krate in report["summary"]["crates"]
+        .as_array()
+        .context("missing crate summary")?
+    {
+        if krate["ice"].as_bool() == Some(true) {
+            let name = krate["crate"].as_str().context("missing crate name")?;
+            crates.push(name.to_owned());
+        }
+    }
+    crates.sort();
+    Ok(crates)
+}
+
+/// Reads the crates which were either checked or skipped by a previous JSON report.
+pub fn read_done_crates(path: &Path) -> Result<HashSet<String>> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("error reading file `{}`", path.display()))?;
+    parse_done_crates(&contents)
+        .with_context(|| format!("error parsing report `{}`", path.display()))
+}
+
+fn parse_done_crates(contents: &str) -> Result<HashSet<String>> {
+    let report: Value = serde_json::from_str(contents)?;
+    let summary = &report["summary"];
+    let mut crates = HashSet::new();
+    for krate in summary["checked"]
+        .as_array()
+        .context("missing checked crate list")?
+    {
+        crates.insert(krate.as_str().context("missing crate name")?.to_owned());
+    }
+    for krate in summary["skipped"].as_array().into_iter().flatten() {
+        crates.insert(
+            krate["crate"]
+                .as_str()
+                .context("missing crate name")?
+                .to_owned(),
+        );
+    }
+    Ok(crates)
+}
+
+/// A lint which fired on more crates than allowed by the baseline.
+pub struct Regression {
+    pub lint: String,
+    pub baseline: usize,
+    pub current: usize,
+}
+impl fmt::Display for Regression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: fired on {} crates, baseline {}",
+            self.lint, self.current, self.baseline
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_done_crates, parse_ice_crates, Baseline};
+    use crate::SkipReason;
+    use std::collections::{HashMap, HashSet};
+
+    #[test]
+    fn regressions() {
+        let baseline = Baseline::parse(
+            r#"{
+                "crates": [
+                    { "crate": "a-1.0.0", "warnings": [{ "lint": "clippy::foo", "message": "" }] },
+                    { "crate": "b-1.0.0", "warnings": [{ "lint": "clippy::bar", "message": "" }] },
+                    { "crate": "gone-1.0.0", "warnings": [{ "lint": "clippy::foo", "message": "" }] }
+                ],
+                "summary": {
+                    "lints": { "clippy::foo": 2, "clippy::bar": 1 },
+                    "skipped": [{ "crate": "c-1.0.0", "reason": "error" }]
+                }
+            }"#,
+        )
+        .unwrap();
+        let set = |crates: &[&str]| crates.iter().map(|&x| x.to_owned()).collect::<HashSet<_>>();
+        let checked = set(&["a-1.0.0", "b-1.0.0", "c-1.0.0", "d-1.0.0", "e-1.0.0"]);
+        let lints = HashMap::from([
+            (
+                "clippy::foo".to_owned(),
+                set(&["a-1.0.0", "c-1.0.0", "d-1.0.0"]),
+            ),
+            ("clippy::bar".to_owned(), set(&["b-1.0.0", "e-1.0.0"])),
+            ("clippy::new".to_owned(), set(&["a-1.0.0", "b-1.0.0"])),
+        ]);
+        let skipped = [("e-1.0.0".to_owned(), SkipReason::Error)];
+
+        let res = baseline.regressions(&lints, &checked, &skipped, 0);
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].lint, "clippy::foo");
+        assert_eq!((res[0].baseline, res[0].current), (1, 2));
+        assert!(baseline
+            .regressions(&lints, &checked, &skipped, 1)
+            .is_empty());
+    }
+
+    #[test]
+    fn ice_crates() {
+        let crates = parse_ice_crates(
+            r#"{
+                "crates": [],
+                "summary": {
+                    "crates": [
+                        { "crate": "b-1.0.0", "warnings": 0, "ice": true },
+                        { "crate": "c-1.0.0", "warnings": 2, "ice": false },
+                        { "crate": "a-1.0.0", "warnings": 1, "ice": true }
+                    ]
+                }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(crates, ["a-1.0.0", "b-1.0.0"]);
+    }
+
+    #[test]
+    fn done_crates() {
+        let crates = parse_done_crates(
+            r#"{
+                "crates": [],
+                "summary": {
+                    "checked": ["a-1.0.0", "b-1.0.0"],
+                    "skipped": [{ "crate": "c-1.0.0", "reason": "rustc-ap" }]
+                }
+            }"#,
+        )
+        .unwrap();
+        let mut crates: Vec<_> = crates.into_iter().collect();
+        crates.sort();
+        assert_eq!(crates, ["a-1.0.0", "b-1.0.0", "c-1.0.0"]);
+        assert!(parse_done_crates(r#"{ "summary": {} }"#).is_err());
+    }
+}
@@ -1,61 +1,110 @@
-use anyhow::{Context, Result};
-use clippy_lint_test::{is_rustc_crate, LatestVersions, Version};
-use csv::{ReaderBuilder, StringRecord};
+use anyhow::{bail, Context, Result};
+use clippy_lint_test::{
+    dump::{read_crates, read_indexed, read_merged, read_versions},
+    fetch::Fetcher,
+    init_cargo, normalize_crate_name,
+};
 use std::{
-    collections::HashMap,
+    collections::{hash_map::RandomState, HashSet},
     fs,
+    hash::BuildHasher,
     io::{self, Write},
     path::{Path, PathBuf},
-    process::Command,
+    thread,
+    time::Duration,
 };
-use temp_dir::TempDir;
 
 #[derive(argh::FromArgs)]
 /// Download the top crates into cargo's crate cache
 struct Args {
     /// path containing the crates.io data dump
     #[argh(positional)]
-    dump_path: PathBuf,
+    dump_path: Option<PathBuf>,
+
+    /// additional crates.io data dumps. Crates found in multiple dumps use the data from the newest
+    /// dump, with the versions from each dump merged
+    #[argh(option, long = "dump-path")]
+    dump_paths: Vec<PathBuf>,
 
-    /// the number of crates to download
+    /// the number of crates to download. Only the crates from `--names-from` are downloaded if
+    /// it's given without this (default 500)
     #[argh(option, short = 'n')]
     count: Option<usize>,
+
+    /// file containing the names of crates to download, one per line. Each line can also be a
+    /// JSON object with a `name` field
+    #[argh(option, long = "names-from")]
+    names_from: Option<PathBuf>,
+
+    /// cache the latest versions of each crate next to the data dump and reuse them until the dump
+    /// changes
+    #[argh(switch, long = "dedup-versions-across-runs")]
+    dedup_versions_across_runs: bool,
+
+    /// the `cargo` binary to fetch crates with (default `$CARGO`, or `cargo` from `PATH`)
+    #[argh(option, long = "cargo")]
+    cargo: Option<PathBuf>,
+
+    /// the number of milliseconds to wait between fetches. Up to a quarter more is added at random
+    /// to each wait. Rate limited fetches are retried with an exponential backoff starting at one
+    /// second or twice this delay, whichever is longer
+    #[argh(option, long = "delay", default = "0")]
+    delay: u64,
 }
 
 fn main() -> Result<()> {
-    let args: Args = argh::from_env();
-    let count = args.count.unwrap_or(500);
+    let mut args: Args = argh::from_env();
+    init_cargo(args.cargo.as_deref());
+    args.dump_paths.extend(args.dump_path.take());
+    if args.dump_paths.is_empty() {
+        bail!("no data dump specified");
+    }
+    let names = args.names_from.as_deref().map(read_names).transpose()?;
+    let count = match (args.count, &names) {
+        (Some(count), _) => count,
+        (None, Some(_)) => 0,
+        (None, None) => 500,
+    };
 
-    let versions = read_versions(&args.dump_path);
-    let mut crates = read_crates(&args.dump_path);
-    let crates = if crates.len() <= count {
-        crates.as_slice()
+    let (mut crates, versions) = if args.dedup_versions_across_runs {
+        read_merged(&args.dump_paths, read_indexed)
     } else {
-        crates
-            .select_nth_unstable_by(count, |x, y| {
-                x.download_count.cmp(&y.download_count).reverse()
-            })
-            .0
+        read_merged(&args.dump_paths, |p| (read_crates(p), read_versions(p)))
     };
+    if crates.len() > count {
+        crates.select_nth_unstable_by(count, |x, y| {
+            x.download_count.cmp(&y.download_count).reverse()
+        });
+    }
+    let (top, rest) = crates.split_at(count.min(crates.len()));
+    let mut crates: Vec<_> = top.iter().collect();
+    if let Some(names) = &names {
+        let mut found: HashSet<_> = top
+            .iter()
+            .map(|c| normalize_crate_name(&c.name))
+            .filter(|name| names.contains(name))
+            .collect();
+        for c in rest {
+            let name = normalize_crate_name(&c.name);
+            if names.contains(&name) {
+                crates.push(c);
+                found.insert(name);
+            }
+        }
+        for name in names.difference(&found) {
+            eprintln!("crate `{}` not found in the data dump", name);
+        }
+    }
 
-    let dir = TempDir::new().context("error creating temp dir")?;
-    let temp_path = dir.path();
-
-    fs::create_dir(temp_path.join("src")).context("error creating item in temp dir")?;
-    fs::File::create(temp_path.join("src").join("lib.rs"))
-        .context("error creating item in temp dir")?;
-    let toml_path = temp_path.join("Cargo.toml");
-
-    let cargo_home =
-        home::cargo_home_with_cwd(temp_path).context("error getting cargo home dir")?;
-    let crates_io_cache = cargo_home
-        .join("registry")
-        .join("cache")
-        .join("github.com-1ecc6299db9ec823");
+    let fetcher = Fetcher::new(false)?;
+    let crates_io_cache = fetcher.cache_dir()?;
+    let delay = Duration::from_millis(args.delay);
+    let backoff = (delay * 2).max(Duration::from_secs(1));
+    let random = RandomState::new();
 
     // Dependencies likely have more downloads than dependant crates.
     // Download in reverse order to reduce the number of `cargo fetch` calls.
-    for (i, id) in crates
+    for (fetched, (i, id)) in crates
         .iter()
         .rev()
         .enumerate()
@@ -66,41 +115,19 @@ fn main() -> Result<()> {
                 .map(move |v| v.iter_ids(name).map(move |id| (i, id)))
         })
         .flatten()
-        .filter(|(_, id)| !crates_io_cache.join(format!("{}.crate", id)).exists())
+        .filter(|(_, id)| !crates_io_cache.join(id.cache_file_name()).exists())
+        .enumerate()
     {
+        if fetched != 0 && !delay.is_zero() {
+            let jitter = random.hash_one(fetched) % (args.delay / 4 + 1);
+            thread::sleep(delay + Duration::from_millis(jitter));
+        }
+
         println!("fetching `{}`", id);
         print!("{}/{}\r", i + 1, crates.len());
         let _ = io::stdout().flush();
 
-        let mut toml_file = fs::OpenOptions::new()
-            .create(true)
-            .truncate(true)
-            .write(true)
-            .open(&toml_path)
-            .context("error creating item in temp dir")?;
-
-        write!(
-            toml_file,
-            "[package]
-                name = \"package\"
-                version = \"0.1.0\"
-
-                [dependencies]
-                {} = \"{}\"
-                ",
-            id.name, id.version
-        )
-        .context("error writing item in temp dir")?;
-
-        drop(toml_file);
-        if !Command::new("cargo")
-            .arg("fetch")
-            .current_dir(temp_path)
-            .output()
-            .unwrap()
-            .status
-            .success()
-        {
+        if !fetcher.fetch_with_backoff(&id, backoff)? {
             eprintln!("error fetching dependencies");
         }
     }
@@ -108,82 +135,24 @@ fn main() -> Result<()> {
     Ok(())
 }
 
-struct Crate {
-    id: u64,
-    name: String,
-    download_count: u64,
-}
-
-/// Parses the versions database to extract the latest version number for each crate.
-fn read_versions(p: &Path) -> HashMap<u64, LatestVersions> {
-    let mut csv = ReaderBuilder::new()
-        .has_headers(true)
-        .from_path(p.join("versions.csv"))
-        .expect("error reading versions.csv");
-
-    let headers = ["crate_id", "num", "yanked"];
-    let indicies = headers_to_indicies(csv.headers().expect("error reading file header"), headers);
-    let mut result = HashMap::<_, LatestVersions>::new();
-    for r in csv.into_records() {
-        let r = r.expect("error reading record");
-        let data = extract_indicies(&r, indicies);
-        if data[2] == "t" {
-            continue;
-        }
-        let id = data[0].parse().expect("error parsing crate id");
-        if let Some(version) = Version::parse(data[1]) {
-            result.entry(id).or_default().push(version);
-        }
-    }
-    result
-}
-
-/// Parses the crates database to extract the crate name and download count.
-fn read_crates(p: &Path) -> Vec<Crate> {
-    let mut csv = ReaderBuilder::new()
-        .has_headers(true)
-        .from_path(p.join("crates.csv"))
-        .expect("error reading crates.csv");
-
-    let headers = ["downloads", "id", "name"];
-    let indicies = headers_to_indicies(csv.headers().expect("error reading file header"), headers);
-    csv.into_records()
-        .filter_map(|r| {
-            let r = r.expect("error reading record");
-            let data = extract_indicies(&r, indicies);
-            if is_rustc_crate(data[2]) {
-                return None;
-            }
-            let download_count = data[0].parse().expect("error parsing crate id");
-            let id = data[1].parse().expect("error parsing crate id");
-            let name = data[2].into();
-            Some(Crate {
-                id,
-                name,
-                download_count,
-            })
-        })
-        .collect()
-}
-
-/// Converts a list of header names to their column indicies.
-fn headers_to_indicies<const N: usize>(r: &StringRecord, headers: [&'static str; N]) -> [usize; N] {
-    let mut found = [None; N];
-    for (i, field) in r.iter().enumerate() {
-        if let Some(which) = headers.iter().position(|&h| h == field) {
-            found[which] = Some(i);
-        }
-    }
-    found.map(|x| x.expect("failed to find header"))
-}
-
-/// Extracts the data for the the given indicies
-fn extract_indicies<const N: usize>(r: &StringRecord, indicies: [usize; N]) -> [&str; N] {
-    let mut found = [None; N];
-    for (i, field) in r.iter().enumerate() {
-        if let Some(which) = indicies.iter().position(|&index| index == i) {
-            found[which] = Some(field);
-        }
+/// Reads a list of crate names from a file. Each line is either a crate name or a JSON object with a
+/// `name` field. Names are returned normalized.
+fn read_names(p: &Path) -> Result<HashSet<String>> {
+    let contents =
+        fs::read_to_string(p).with_context(|| format!("error reading `{}`", p.display()))?;
+    let mut names = HashSet::new();
+    for line in contents.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        let name = if line.starts_with('{') {
+            let value: serde_json::Value = serde_json::from_str(line)
+                .with_context(|| format!("error parsing line `{}`", line))?;
+            value["name"]
+                .as_str()
+                .with_context(|| format!("missing field `name` on line `{}`", line))?
+                .to_owned()
+        } else {
+            line.to_owned()
+        };
+        names.insert(normalize_crate_name(&name));
     }
-    found.map(|x| x.expect("failed to find header value"))
+    Ok(names)
 }
@@ -0,0 +1,102 @@
+//! Crates which failed to build in a previous run.
+
+use anyhow::{Context, Result};
+use std::{
+    collections::HashSet,
+    fs,
+    io::{self, Write},
+    path::PathBuf,
+};
+
+/// The name of the blocklist file written next to the report.
+pub const FILE_NAME: &str = "build-blocklist.txt";
+
+/// A list of crates which failed to build, stored one per line.
+pub struct Blocklist {
+    path: PathBuf,
+    crates: HashSet<String>,
+    /// Whether any crates were removed and the file needs to be rewritten.
+    removed: bool,
+}
+impl Blocklist {
+    /// Reads the blocklist at the given path. A missing file is an empty list.
+    pub fn read(path: PathBuf) -> Result<Self> {
+        let crates = match fs::read_to_string(&path) {
+            Ok(contents) => contents
+                .lines()
+                .map(str::trim)
+                .filter(|l| !l.is_empty())
+                .map(str::to_owned)
+                .collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashSet::new(),
+            Err(e) => {
+                return Err(e).with_context(|| format!("error reading file `{}`", path.display()))
+            }
+        };
+        Ok(Self {
+            path,
+            crates,
+            removed: false,
+        })
+    }
+
+    pub fn contains(&self, krate: &str) -> bool {
+        self.crates.contains(krate)
+    }
+
+    /// Adds a crate, appending it to the file immediately so it's kept if the run is interrupted.
+    pub fn insert(&mut self, krate: &str) -> Result<()> {
+        if self.crates.insert(krate.to_owned()) {
+            fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+                .and_then(|mut file| writeln!(file, "{}", krate))
+                .with_context(|| format!("error writing file `{}`", self.path.display()))?;
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, krate: &str) {
+        self.removed |= self.crates.remove(krate);
+    }
+
+    /// Rewrites the file if any crates were removed.
+    pub fn save(&self) -> Result<()> {
+        if !self.removed {
+            return Ok(());
+        }
+        let mut crates: Vec<_> = self.crates.iter().map(String::as_str).collect();
+        crates.sort_unstable();
+        let mut contents = crates.join("\n");
+        if !contents.is_empty() {
+            contents.push('\n');
+        }
+        fs::write(&self.path, contents)
+            .with_context(|| format!("error writing file `{}`", self.path.display()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Blocklist;
+    use std::fs;
+
+    #[test]
+    fn blocklist() {
+        let dir = temp_dir::TempDir::new().unwrap();
+        let path = dir.path().join(super::FILE_NAME);
+        let mut list = Blocklist::read(path.clone()).unwrap();
+        assert!(!list.contains("a-1.0.0"));
+        list.insert("b-1.0.0").unwrap();
+        list.insert("a-1.0.0").unwrap();
+        list.insert("b-1.0.0").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "b-1.0.0\na-1.0.0\n");
+
+        let mut list = Blocklist::read(path.clone()).unwrap();
+        assert!(list.contains("a-1.0.0"));
+        list.remove("b-1.0.0");
+        list.save().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "a-1.0.0\n");
+    }
+}
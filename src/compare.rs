@@ -0,0 +1,114 @@
+//! Differences between the results of two clippy builds on the same crate.
+
+use std::collections::HashMap;
+
+/// The messages and ICEs which differ between the tested clippy and the one it's compared with.
+pub struct CrateDiff {
+    /// Messages only emitted by the tested clippy.
+    pub added: Vec<(String, String)>,
+    /// Messages only emitted by the compared clippy.
+    pub removed: Vec<(String, String)>,
+    /// Only the tested clippy caused an ICE.
+    pub new_ice: bool,
+    /// Only the compared clippy caused an ICE.
+    pub fixed_ice: bool,
+}
+impl CrateDiff {
+    /// Compares the results of both clippy builds. Returns `None` if they're the same.
+    pub fn new(
+        msgs: &[(String, String)],
+        ice: bool,
+        base_msgs: &[(String, String)],
+        base_ice: bool,
+    ) -> Option<Self> {
+        let res = Self {
+            added: difference(msgs, base_msgs),
+            removed: difference(base_msgs, msgs),
+            new_ice: ice && !base_ice,
+            fixed_ice: base_ice && !ice,
+        };
+        (!res.added.is_empty() || !res.removed.is_empty() || res.new_ice || res.fixed_ice)
+            .then_some(res)
+    }
+}
+
+/// Finds the messages in `x` which aren't in `y`. Repeated messages are counted separately.
+fn difference(x: &[(String, String)], y: &[(String, String)]) -> Vec<(String, String)> {
+    let mut counts = HashMap::<_, usize>::new();
+    for msg in y {
+        *counts.entry(msg).or_default() += 1;
+    }
+    x.iter()
+        .filter(|&msg| match counts.get_mut(msg) {
+            Some(count) if *count > 0 => {
+                *count -= 1;
+                false
+            }
+            _ => true,
+        })
+        .cloned()
+        .collect()
+}
+
+/// The differences across all crates.
+#[derive(Default)]
+pub struct DiffTotals {
+    /// The number of added and removed messages for each lint.
+    pub lints: HashMap<String, (usize, usize)>,
+    pub new_ices: Vec<String>,
+    pub fixed_ices: Vec<String>,
+}
+impl DiffTotals {
+    pub fn add(&mut self, krate: &str, diff: &CrateDiff) {
+        for (lint, _) in &diff.added {
+            self.lints.entry(lint.clone()).or_default().0 += 1;
+        }
+        for (lint, _) in &diff.removed {
+            self.lints.entry(lint.clone()).or_default().1 += 1;
+        }
+        if diff.new_ice {
+            self.new_ices.push(krate.into());
+        }
+        if diff.fixed_ice {
+            self.fixed_ices.push(krate.into());
+        }
+    }
+
+    /// Gets the added and removed counts of each lint, sorted by lint name.
+    pub fn sorted_lints(&self) -> Vec<(&str, usize, usize)> {
+        let mut res: Vec<_> = self
+            .lints
+            .iter()
+            .map(|(lint, &(added, removed))| (lint.as_str(), added, removed))
+            .collect();
+        res.sort_by(|x, y| x.0.cmp(y.0));
+        res
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CrateDiff, DiffTotals};
+
+    #[test]
+    fn crate_diff() {
+        let msg = |lint: &str, msg: &str| (lint.to_owned(), msg.to_owned());
+        let msgs = [msg("a", "1"), msg("a", "1"), msg("b", "2")];
+        let base = [msg("a", "1"), msg("c", "3")];
+
+        let diff = CrateDiff::new(&msgs, true, &base, false).unwrap();
+        assert_eq!(diff.added, [msg("a", "1"), msg("b", "2")]);
+        assert_eq!(diff.removed, [msg("c", "3")]);
+        assert!(diff.new_ice && !diff.fixed_ice);
+
+        let mut totals = DiffTotals::default();
+        totals.add("foo-1.0.0", &diff);
+        assert_eq!(
+            totals.sorted_lints(),
+            [("a", 1, 0), ("b", 1, 0), ("c", 0, 1)]
+        );
+        assert_eq!(totals.new_ices, ["foo-1.0.0"]);
+
+        assert!(CrateDiff::new(&msgs, false, &msgs, false).is_none());
+    }
+}
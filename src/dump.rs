@@ -0,0 +1,381 @@
+//! Parsing for the crates.io database dump.
+
+use crate::{is_rustc_crate, LatestVersions, Version};
+use csv::{ReaderBuilder, StringRecord};
+use std::{
+    cmp,
+    collections::HashMap,
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// A crate's entry in the crates database.
+pub struct Crate {
+    pub id: u64,
+    pub name: String,
+    pub download_count: u64,
+}
+
+/// Parses the versions database to extract the latest version number for each crate.
+pub fn read_versions(p: &Path) -> HashMap<u64, LatestVersions> {
+    let data = fs::read(p.join("versions.csv")).expect("error reading versions.csv");
+    let (headers_record, chunks) = split_csv(&data);
+
+    let columns = Columns::new(&headers_record, ["crate_id", "num", "yanked"]);
+    parse_chunks(&chunks, |chunk| {
+        let mut result = HashMap::<_, LatestVersions>::new();
+        for r in chunk.into_records() {
+            let r = r.expect("error reading record");
+            let [id, num, yanked] = columns.extract(&r);
+            add_version(&mut result, id, num, yanked);
+        }
+        result
+    })
+    .reduce(|mut result, other| {
+        for (id, versions) in other {
+            result.entry(id).or_default().merge(&versions);
+        }
+        result
+    })
+    .unwrap_or_default()
+}
+
+/// Adds a single record from the versions database to the latest versions of each crate. This is
+/// run for every record in the database. Only public for the benchmarks.
+#[doc(hidden)]
+pub fn add_version(versions: &mut HashMap<u64, LatestVersions>, id: &str, num: &str, yanked: &str) {
+    if yanked == "t" {
+        return;
+    }
+    let id = parse_field(id, "crate id");
+    if let Some(version) = Version::parse(num) {
+        versions.entry(id).or_default().push(version);
+    }
+}
+
+/// Parses the crates database to extract the crate name and download count.
+pub fn read_crates(p: &Path) -> Vec<Crate> {
+    let data = fs::read(p.join("crates.csv")).expect("error reading crates.csv");
+    let (headers_record, chunks) = split_csv(&data);
+
+    let columns = Columns::new(&headers_record, ["downloads", "id", "name"]);
+    parse_chunks(&chunks, |chunk| {
+        chunk
+            .into_records()
+            .filter_map(|r| {
+                let r = r.expect("error reading record");
+                let [downloads, id, name] = columns.extract(&r);
+                if is_rustc_crate(name) {
+                    return None;
+                }
+                Some(Crate {
+                    id: parse_field(id, "crate id"),
+                    name: name.into(),
+                    download_count: parse_field(downloads, "download count"),
+                })
+            })
+            .collect::<Vec<_>>()
+    })
+    .reduce(|mut result, other| {
+        result.extend(other);
+        result
+    })
+    .unwrap_or_default()
+}
+
+/// The name of the file the parsed crates and versions are cached in, relative to the dump.
+const INDEX_FILE: &str = "clippy_lint_test.index";
+/// The first field of the index header. Change this if the format changes.
+const INDEX_VERSION: &str = "index-v1";
+
+/// Reads both the crates and their latest versions. The results are cached next to the dump and
+/// reused until either csv file is modified.
+pub fn read_indexed(p: &Path) -> (Vec<Crate>, HashMap<u64, LatestVersions>) {
+    let header = index_header(p);
+    let index_path = p.join(INDEX_FILE);
+    if let Some(index) = header.as_deref().and_then(|header| {
+        let contents = fs::read_to_string(&index_path).ok()?;
+        parse_index(&contents, header)
+    }) {
+        return index;
+    }
+
+    let versions = read_versions(p);
+    let crates = read_crates(p);
+    if let Some(header) = header {
+        let mut contents = header;
+        contents.push('\n');
+        for c in &crates {
+            let _ = write!(contents, "{}\t{}\t{}\t", c.id, c.download_count, c.name);
+            if let Some(versions) = versions.get(&c.id) {
+                for (i, id) in versions.iter_ids("").enumerate() {
+                    let sep = if i == 0 { "" } else { " " };
+                    let _ = write!(contents, "{}{}", sep, id.version);
+                }
+            }
+            contents.push('\n');
+        }
+        if let Err(e) = fs::write(&index_path, contents) {
+            eprintln!("error writing `{}`: {}", index_path.display(), e);
+        }
+    }
+    (crates, versions)
+}
+
+/// Reads the crates and their latest versions from multiple dumps using the given function. When
+/// a crate is in several dumps the record from the newest dump is used, and the versions from all
+/// of them are merged. Dumps are ordered by the modification time of `crates.csv`.
+pub fn read_merged(
+    paths: &[PathBuf],
+    read: impl Fn(&Path) -> (Vec<Crate>, HashMap<u64, LatestVersions>),
+) -> (Vec<Crate>, HashMap<u64, LatestVersions>) {
+    let mut paths: Vec<_> = paths.iter().collect();
+    paths.sort_by_cached_key(|p| cmp::Reverse(dump_time(p)));
+
+    let mut crates = HashMap::<u64, Crate>::new();
+    let mut versions = HashMap::<u64, LatestVersions>::new();
+    for p in paths {
+        let (dump_crates, dump_versions) = read(p);
+        for c in dump_crates {
+            crates.entry(c.id).or_insert(c);
+        }
+        for (id, dump_versions) in dump_versions {
+            versions.entry(id).or_default().merge(&dump_versions);
+        }
+    }
+    (crates.into_values().collect(), versions)
+}
+
+fn dump_time(p: &Path) -> Option<SystemTime> {
+    fs::metadata(p.join("crates.csv")).ok()?.modified().ok()
+}
+
+/// Creates the header line for the index, which contains the modification time of both csv files.
+fn index_header(p: &Path) -> Option<String> {
+    let mtime = |name: &str| {
+        let time = fs::metadata(p.join(name)).ok()?.modified().ok()?;
+        Some(time.duration_since(UNIX_EPOCH).ok()?.as_nanos())
+    };
+    Some(format!(
+        "{} {} {}",
+        INDEX_VERSION,
+        mtime("crates.csv")?,
+        mtime("versions.csv")?
+    ))
+}
+
+/// Parses the cached index. Returns `None` if it's invalid or was created from a different dump.
+fn parse_index(contents: &str, header: &str) -> Option<(Vec<Crate>, HashMap<u64, LatestVersions>)> {
+    let mut lines = contents.lines();
+    if lines.next()? != header {
+        return None;
+    }
+    let mut crates = Vec::new();
+    let mut versions = HashMap::new();
+    for line in lines {
+        let mut fields = line.splitn(4, '\t');
+        let id = fields.next()?.parse().ok()?;
+        let download_count = fields.next()?.parse().ok()?;
+        let name = fields.next()?.into();
+        let mut latest = LatestVersions::default();
+        for version in fields.next()?.split(' ').filter(|v| !v.is_empty()) {
+            latest.push(Version::parse(version)?);
+        }
+        versions.insert(id, latest);
+        crates.push(Crate {
+            id,
+            name,
+            download_count,
+        });
+    }
+    Some((crates, versions))
+}
+
+/// Reads the header of a csv file and splits the remaining data into one chunk per thread. Each
+/// chunk starts and ends on a record boundary.
+fn split_csv(data: &[u8]) -> (StringRecord, Vec<&[u8]>) {
+    let mut csv = ReaderBuilder::new().has_headers(true).from_reader(data);
+    let headers = csv.headers().expect("error reading file header").clone();
+    let data = &data[csv.position().byte() as usize..];
+
+    let thread_count = thread::available_parallelism().map_or(1, |x| x.get());
+    let chunk_size = data.len() / thread_count + 1;
+    let mut chunks = Vec::with_capacity(thread_count);
+    let mut start = 0;
+    let mut in_quotes = false;
+    for (i, &c) in data.iter().enumerate() {
+        match c {
+            // Escaped quotes toggle the state twice.
+            b'"' => in_quotes = !in_quotes,
+            b'\n' if !in_quotes && i + 1 - start >= chunk_size => {
+                chunks.push(&data[start..=i]);
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    if start < data.len() {
+        chunks.push(&data[start..]);
+    }
+    (headers, chunks)
+}
+
+/// Parses each chunk of a csv file on it's own thread.
+fn parse_chunks<'a, T: Send>(
+    chunks: &[&'a [u8]],
+    f: impl Fn(csv::Reader<&'a [u8]>) -> T + Sync,
+) -> impl Iterator<Item = T> {
+    let f = &f;
+    thread::scope(|s| {
+        chunks
+            .iter()
+            .map(|&chunk| {
+                s.spawn(move || f(ReaderBuilder::new().has_headers(false).from_reader(chunk)))
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|t| t.join().expect("error parsing csv file"))
+            .collect::<Vec<_>>()
+    })
+    .into_iter()
+}
+
+/// The indicies of a set of named columns in a csv file from the dump.
+pub struct Columns<const N: usize>([usize; N]);
+impl<const N: usize> Columns<N> {
+    /// Finds the index of each named column in the header record. Panics if any are missing.
+    pub fn new(headers: &StringRecord, names: [&str; N]) -> Self {
+        Self(names.map(|name| {
+            headers
+                .iter()
+                .position(|h| h == name)
+                .unwrap_or_else(|| panic!("failed to find header `{}`", name))
+        }))
+    }
+
+    /// Extracts the field for each column, in the order the columns were named.
+    pub fn extract<'a>(&self, r: &'a StringRecord) -> [&'a str; N] {
+        self.0
+            .map(|i| r.get(i).expect("failed to find header value"))
+    }
+}
+
+/// Parses a field from a record, panicking on failure. `what` names the field in the message.
+pub fn parse_field<T: FromStr>(field: &str, what: &str) -> T {
+    field
+        .parse()
+        .unwrap_or_else(|_| panic!("error parsing {} `{}`", what, field))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        read_crates, read_indexed, read_merged, read_versions, Columns, Crate, INDEX_FILE,
+    };
+    use crate::LatestVersions;
+    use csv::StringRecord;
+    use std::{
+        collections::HashMap,
+        fs,
+        path::Path,
+        time::{Duration, SystemTime},
+    };
+
+    #[test]
+    fn extract_columns() {
+        let headers = StringRecord::from(vec!["id", "name", "downloads", "extra"]);
+        let columns = Columns::new(&headers, ["downloads", "id", "name"]);
+        let record = StringRecord::from(vec!["1", "foo", "100", "x"]);
+        assert_eq!(columns.extract(&record), ["100", "1", "foo"]);
+    }
+
+    #[test]
+    fn cached_index() {
+        let dir = temp_dir::TempDir::new().unwrap();
+        fs::write(
+            dir.path().join("crates.csv"),
+            "downloads,id,name\n100,1,foo\n5,2,bar\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("versions.csv"),
+            "crate_id,num,yanked\n1,1.0.0,f\n1,1.1.0-beta.1,f\n2,0.1.0+meta,f\n2,0.2.0,t\n",
+        )
+        .unwrap();
+
+        let ids = |(crates, versions): (Vec<Crate>, HashMap<u64, LatestVersions>)| {
+            let mut ids: Vec<_> = crates
+                .iter()
+                .flat_map(|c| {
+                    versions[&c.id]
+                        .iter_ids(&c.name)
+                        .map(|id| format!("{} {}", id, c.download_count))
+                        .collect::<Vec<_>>()
+                })
+                .collect();
+            ids.sort();
+            ids
+        };
+        let expected = ["bar-0.1.0+meta 5", "foo-1.0.0 100", "foo-1.1.0-beta.1 100"];
+        assert_eq!(ids(read_indexed(dir.path())), expected);
+        assert!(dir.path().join(INDEX_FILE).exists());
+
+        // The second read must come from the index.
+        fs::write(dir.path().join(INDEX_FILE), {
+            let contents = fs::read_to_string(dir.path().join(INDEX_FILE)).unwrap();
+            contents.replace("\t100\t", "\t101\t")
+        })
+        .unwrap();
+        let expected = ["bar-0.1.0+meta 5", "foo-1.0.0 101", "foo-1.1.0-beta.1 101"];
+        assert_eq!(ids(read_indexed(dir.path())), expected);
+    }
+
+    #[test]
+    fn merged_dumps() {
+        let old = temp_dir::TempDir::new().unwrap();
+        let new = temp_dir::TempDir::new().unwrap();
+        let write = |dir: &temp_dir::TempDir, crates: &str, versions: &str, age: u64| {
+            fs::write(dir.path().join("crates.csv"), crates).unwrap();
+            fs::write(dir.path().join("versions.csv"), versions).unwrap();
+            fs::File::options()
+                .write(true)
+                .open(dir.path().join("crates.csv"))
+                .unwrap()
+                .set_modified(SystemTime::now() - Duration::from_secs(age))
+                .unwrap();
+        };
+        write(
+            &old,
+            "downloads,id,name\n50,1,foo\n5,2,bar\n",
+            "crate_id,num,yanked\n1,1.0.0,f\n1,1.1.0-beta.1,f\n2,0.1.0,f\n",
+            1000,
+        );
+        write(
+            &new,
+            "downloads,id,name\n100,1,foo\n",
+            "crate_id,num,yanked\n1,1.0.1,f\n",
+            0,
+        );
+
+        let read = |p: &Path| (read_crates(p), read_versions(p));
+        let (crates, versions) = read_merged(&[old.path().into(), new.path().into()], read);
+        let mut ids: Vec<_> = crates
+            .iter()
+            .flat_map(|c| {
+                versions[&c.id]
+                    .iter_ids(&c.name)
+                    .map(|id| format!("{} {}", id, c.download_count))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        ids.sort();
+        assert_eq!(
+            ids,
+            ["bar-0.1.0 5", "foo-1.0.1 100", "foo-1.1.0-beta.1 100"]
+        );
+    }
+}
@@ -0,0 +1,137 @@
+//! Downloading crates into cargo's crate cache.
+
+use crate::{cargo_command, CrateId};
+use anyhow::{Context, Result};
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+use temp_dir::TempDir;
+
+/// The number of times a rate limited fetch is retried.
+const MAX_RATE_LIMIT_RETRIES: u32 = 5;
+
+/// A temporary package used to download crates with `cargo fetch`.
+pub struct Fetcher {
+    dir: TempDir,
+    /// Whether to fetch exactly the requested version rather than the newest compatible one.
+    exact_version: bool,
+}
+impl Fetcher {
+    pub fn new(exact_version: bool) -> Result<Self> {
+        let dir = TempDir::new().context("error creating temp dir")?;
+        fs::create_dir(dir.path().join("src")).context("error creating item in temp dir")?;
+        fs::File::create(dir.path().join("src").join("lib.rs"))
+            .context("error creating item in temp dir")?;
+        Ok(Self { dir, exact_version })
+    }
+
+    fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// Gets the directory cargo downloads crates.io crates into.
+    pub fn cache_dir(&self) -> Result<PathBuf> {
+        Ok(home::cargo_home_with_cwd(self.path())
+            .context("error getting cargo home dir")?
+            .join("registry")
+            .join("cache")
+            .join("github.com-1ecc6299db9ec823"))
+    }
+
+    /// Downloads the given crate along with all of it's dependencies. Returns whether
+    /// `cargo fetch` succeeded.
+    pub fn fetch(&self, id: &CrateId) -> Result<bool> {
+        self.run_fetch(id).map(|res| res == FetchResult::Success)
+    }
+
+    /// Same as `fetch`, but retries with an exponential backoff starting at `backoff` when the
+    /// registry responds with HTTP 429.
+    pub fn fetch_with_backoff(&self, id: &CrateId, mut backoff: Duration) -> Result<bool> {
+        for _ in 0..MAX_RATE_LIMIT_RETRIES {
+            match self.run_fetch(id)? {
+                FetchResult::RateLimited => {
+                    eprintln!(
+                        "rate limited while fetching `{}`, retrying in {}s",
+                        id,
+                        backoff.as_secs_f64()
+                    );
+                    thread::sleep(backoff);
+                    backoff *= 2;
+                }
+                res => return Ok(res == FetchResult::Success),
+            }
+        }
+        self.fetch(id)
+    }
+
+    fn run_fetch(&self, id: &CrateId) -> Result<FetchResult> {
+        let mut toml_file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(self.path().join("Cargo.toml"))
+            .context("error creating item in temp dir")?;
+
+        write!(
+            toml_file,
+            "[package]
+                name = \"package\"
+                version = \"0.1.0\"
+
+                [dependencies]
+                {} = \"{}{}\"
+                ",
+            id.name,
+            if self.exact_version { "=" } else { "" },
+            id.version
+        )
+        .context("error writing item in temp dir")?;
+        drop(toml_file);
+
+        let output = cargo_command()
+            .arg("fetch")
+            .current_dir(self.path())
+            .output()
+            .context("error running `cargo`")?;
+        Ok(if output.status.success() {
+            FetchResult::Success
+        } else if is_rate_limited(&String::from_utf8_lossy(&output.stderr)) {
+            FetchResult::RateLimited
+        } else {
+            FetchResult::Failed
+        })
+    }
+}
+
+#[derive(PartialEq, Eq)]
+enum FetchResult {
+    Success,
+    Failed,
+    RateLimited,
+}
+
+/// Checks if `cargo fetch` failed due to an HTTP 429 response. Cargo doesn't print the
+/// `Retry-After` header, so only the status can be checked.
+fn is_rate_limited(stderr: &str) -> bool {
+    stderr.contains("got 429") || stderr.contains("429 Too Many Requests")
+}
+
+#[cfg(test)]
+mod test {
+    use super::is_rate_limited;
+
+    #[test]
+    fn rate_limited() {
+        assert!(is_rate_limited(
+            "error: failed to download from `https://static.crates.io/crates/foo/1.0.0/download`\n\n\
+            Caused by:\n  failed to get 200 response from `https://static.crates.io/crates/foo/1.0.0/download`, got 429\n"
+        ));
+        assert!(!is_rate_limited(
+            "error: failed to select a version for the requirement `foo = \"=1.0.0\"`\n"
+        ));
+    }
+}
@@ -1,4 +1,25 @@
 use core::{borrow::Borrow, cmp::Ordering, fmt};
+use std::{env, path::Path, process::Command};
+
+pub mod dump;
+pub mod fetch;
+
+/// Selects the binary run by `cargo_command`. The given path takes priority over the `CARGO`
+/// environment variable. The `CARGO` set by `cargo run` is ignored as it points to the toolchain's
+/// binary rather than rustup's proxy.
+pub fn init_cargo(cargo: Option<&Path>) {
+    if let Some(cargo) = cargo {
+        env::set_var("CARGO", cargo);
+    } else if env::var_os("CARGO_PKG_NAME").is_some_and(|name| name == env!("CARGO_PKG_NAME")) {
+        env::remove_var("CARGO");
+    }
+}
+
+/// Creates a command which runs `cargo`. This is the `CARGO` environment variable if it's set,
+/// otherwise `cargo` from `PATH`. The variable is also used by `cargo-clippy`.
+pub fn cargo_command() -> Command {
+    Command::new(env::var_os("CARGO").unwrap_or_else(|| "cargo".into()))
+}
 
 /// The main part of a version number
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
@@ -7,6 +28,34 @@ pub struct MainVersion {
     pub minor: u16,
     pub patch: u16,
 }
+impl MainVersion {
+    /// Gets the first version which isn't compatible with this one under cargo's caret rules. e.g.
+    /// `2.0.0` for `1.2.3`, `0.3.0` for `0.2.3` and `0.0.4` for `0.0.3`. Returns `None` if the
+    /// bound can't be represented, in which case there is no upper bound.
+    pub fn caret_upper_bound(&self) -> Option<MainVersion> {
+        let zero = MainVersion {
+            major: 0,
+            minor: 0,
+            patch: 0,
+        };
+        Some(if self.major != 0 {
+            MainVersion {
+                major: self.major.checked_add(1)?,
+                ..zero
+            }
+        } else if self.minor != 0 {
+            MainVersion {
+                minor: self.minor.checked_add(1)?,
+                ..zero
+            }
+        } else {
+            MainVersion {
+                patch: self.patch.checked_add(1)?,
+                ..zero
+            }
+        })
+    }
+}
 impl fmt::Display for MainVersion {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
@@ -50,6 +99,37 @@ pub struct Version<T> {
     pre: Option<PreVersion<T>>,
     build: Option<T>,
 }
+impl<T> Version<T> {
+    /// Creates a version number without a pre-release or build part. All versions, including those
+    /// from `Version::parse`, are constructed through this and the `with_*` methods.
+    pub fn new(version: MainVersion) -> Self {
+        Self {
+            version,
+            pre: None,
+            build: None,
+        }
+    }
+
+    /// Replaces the pre-release part.
+    pub fn with_pre(self, pre: Option<PreVersion<T>>) -> Self {
+        Self { pre, ..self }
+    }
+
+    /// Replaces the build metadata.
+    pub fn with_build(self, build: Option<T>) -> Self {
+        Self { build, ..self }
+    }
+
+    /// Gets the main part of the version number.
+    pub fn main(&self) -> MainVersion {
+        self.version
+    }
+
+    /// Gets the pre-release part of the version number.
+    pub fn pre(&self) -> Option<&PreVersion<T>> {
+        self.pre.as_ref()
+    }
+}
 impl<T: Borrow<str>> Version<T> {
     /// Borrows the pre-release stream name.
     pub fn borrow(&self) -> Version<&str> {
@@ -59,6 +139,14 @@ impl<T: Borrow<str>> Version<T> {
             build: self.build.as_ref().map(|b| b.borrow()),
         }
     }
+
+    /// Gets the canonical form of the version number. Leading zeros are removed from all numeric
+    /// parts and build metadata is dropped, as it doesn't distinguish versions. Combined with
+    /// `parse_tolerant` this also removes a leading `v`. `Version::parse(x).canonicalize()` is
+    /// idempotent, so the result can be used as a key for deduplication.
+    pub fn canonicalize(&self) -> String {
+        self.borrow().with_build(None).to_string()
+    }
 }
 impl<T: ?Sized + ToOwned> Version<&'_ T> {
     /// Converts the pre-release stream name to it's owned form.
@@ -86,33 +174,36 @@ impl<'a> Version<&'a str> {
         let major = iter.next()?.parse().ok()?;
         let minor = iter.next()?.parse().ok()?;
         let s = iter.next()?;
-        match s.split_once('-') {
+        let (patch, pre, build) = match s.split_once('-') {
             Some((patch, pre)) => {
                 let (stream, version) = pre.split_once('.')?;
                 let (version, build) = parse_with_build(version)?;
-                Some(Self {
-                    version: MainVersion {
-                        major,
-                        minor,
-                        patch: patch.parse().ok()?,
-                    },
-                    pre: Some(PreVersion { stream, version }),
+                (
+                    patch.parse().ok()?,
+                    Some(PreVersion { stream, version }),
                     build,
-                })
+                )
             }
             None => {
                 let (patch, build) = parse_with_build(s)?;
-                Some(Self {
-                    version: MainVersion {
-                        major,
-                        minor,
-                        patch,
-                    },
-                    pre: None,
-                    build,
-                })
+                (patch, None, build)
             }
-        }
+        };
+        Some(
+            Self::new(MainVersion {
+                major,
+                minor,
+                patch,
+            })
+            .with_pre(pre)
+            .with_build(build),
+        )
+    }
+
+    /// Same as `parse`, but also accepts a single leading `v` or `V` as used by tags, e.g.
+    /// `v1.2.3`. crates.io never uses the prefix, so data from it should use `parse`.
+    pub fn parse_tolerant(s: &'a str) -> Option<Self> {
+        Self::parse(s.strip_prefix(['v', 'V']).unwrap_or(s))
     }
 }
 impl<T: fmt::Display> fmt::Display for Version<T> {
@@ -143,11 +234,7 @@ pub struct LatestVersions {
 impl LatestVersions {
     /// Replaces the current version with the given version if it's newer.
     pub fn push(&mut self, arg: Version<&'_ str>) {
-        if self
-            .stable
-            .as_ref()
-            .map_or(false, |&(v, _)| v >= arg.version)
-        {
+        if self.stable.as_ref().is_some_and(|&(v, _)| v >= arg.version) {
             // current stable version is newer than the incoming version.
             return;
         }
@@ -183,7 +270,7 @@ impl LatestVersions {
             None => {
                 self.stable = Some((arg.version, arg.build.map(|x| x.to_owned())));
                 // Only keep pre-release versions if they're newer than the current stable version.
-                if self.pre.map_or(false, |v| arg.version >= v) {
+                if self.pre.is_some_and(|v| arg.version >= v) {
                     self.pre = None;
                     self.pre_by_stream.clear();
                 }
@@ -191,28 +278,39 @@ impl LatestVersions {
         }
     }
 
+    /// Checks if only pre-release versions have been pushed.
+    pub fn has_only_prereleases(&self) -> bool {
+        self.stable.is_none() && self.pre.is_some()
+    }
+
+    /// Checks if the latest stable version is newer than any pre-release version.
+    pub fn stable_is_latest(&self) -> bool {
+        self.stable.is_some() && self.pre.is_none()
+    }
+
+    /// Merges the versions from another set into this one, keeping only the newest versions.
+    pub fn merge(&mut self, other: &LatestVersions) {
+        for id in other.iter_ids("") {
+            self.push(id.version);
+        }
+    }
+
     /// Gets an iterator over all stable and pre-release versions.
     pub fn iter_ids<'a>(&'a self, name: &'a str) -> impl Iterator<Item = CrateId<'a>> {
         self.stable
             .iter()
             .map(move |&(version, ref build)| CrateId {
                 name,
-                version: Version {
-                    version,
-                    pre: None,
-                    build: build.as_deref(),
-                },
+                version: Version::new(version).with_build(build.as_deref()),
             })
             .chain(self.pre.into_iter().flat_map(move |version| {
                 self.pre_by_stream
                     .iter()
                     .map(move |(prerelease, build)| CrateId {
                         name,
-                        version: Version {
-                            version,
-                            pre: Some(prerelease.borrow()),
-                            build: build.as_deref(),
-                        },
+                        version: Version::new(version)
+                            .with_pre(Some(prerelease.borrow()))
+                            .with_build(build.as_deref()),
                     })
             }))
     }
@@ -239,6 +337,19 @@ impl<'a> CrateId<'a> {
                     })
             })
     }
+
+    /// Parses the name of a `.crate` file, e.g. `serde-1.0.0.crate`. Only the `.crate` extension
+    /// is removed, so names containing dots are parsed correctly.
+    pub fn parse_filename(file_name: &'a str) -> Option<Self> {
+        Self::parse(file_name.strip_suffix(".crate")?)
+    }
+}
+impl CrateId<'_> {
+    /// Gets the name of the file cargo stores the crate in. Build metadata isn't included.
+    pub fn cache_file_name(&self) -> String {
+        let version = self.version.clone().with_build(None);
+        format!("{}-{}.crate", self.name, version)
+    }
 }
 impl fmt::Display for CrateId<'_> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -246,6 +357,12 @@ impl fmt::Display for CrateId<'_> {
     }
 }
 
+/// Normalizes a crate name so that names crates.io considers equivalent compare equal. i.e.
+/// `Foo_Bar` and `foo-bar`.
+pub fn normalize_crate_name(name: &str) -> String {
+    name.to_ascii_lowercase().replace('-', "_")
+}
+
 /// Checks if string names an auto-published rustc crate. These no longer compile.
 pub fn is_rustc_crate(name: &str) -> bool {
     name.starts_with("rustc-ap") | name.starts_with("fast-rustc-ap")
@@ -253,32 +370,20 @@ pub fn is_rustc_crate(name: &str) -> bool {
 
 #[cfg(test)]
 mod test {
-    use super::{LatestVersions, MainVersion, PreVersion, Version};
+    use super::{normalize_crate_name, CrateId, LatestVersions, MainVersion, PreVersion, Version};
 
     macro_rules! version {
-        (@opt) => {
-            None
-        };
-        (@opt $stream:ident:$version:literal) => {
-            Some(PreVersion {
+        ($major:literal:$minor:literal:$patch:literal $(- $stream:ident:$version:literal)? $(+ $build:literal)?) => {
+            Version::new(MainVersion {
+                major: $major,
+                minor: $minor,
+                patch: $patch,
+            })
+            $(.with_pre(Some(PreVersion {
                 stream: stringify!($stream),
                 version: $version,
-            })
-        };
-        (@opt $build:literal) => {
-            Some($build)
-        };
-
-        ($major:literal:$minor:literal:$patch:literal $(- $stream:ident:$version:literal)? $(+ $build:literal)?) => {
-            Version {
-                version: MainVersion {
-                    major: $major,
-                    minor: $minor,
-                    patch: $patch,
-                },
-                pre: version!(@opt $($stream:$version)?),
-                build: version!(@opt $($build)?),
-            }
+            })))?
+            $(.with_build(Some($build)))?
         };
     }
 
@@ -304,6 +409,106 @@ mod test {
         );
     }
 
+    #[test]
+    fn caret_upper_bound() {
+        let v = |major, minor, patch| MainVersion {
+            major,
+            minor,
+            patch,
+        };
+        for (version, expected) in [
+            (v(1, 2, 3), v(2, 0, 0)),
+            (v(1, 0, 0), v(2, 0, 0)),
+            (v(0, 2, 3), v(0, 3, 0)),
+            (v(0, 1, 0), v(0, 2, 0)),
+            (v(0, 0, 3), v(0, 0, 4)),
+            (v(0, 0, 0), v(0, 0, 1)),
+        ] {
+            assert_eq!(version.caret_upper_bound(), Some(expected));
+        }
+        assert_eq!(v(u16::MAX, 2, 3).caret_upper_bound(), None);
+        assert_eq!(v(0, u16::MAX, 3).caret_upper_bound(), None);
+        assert_eq!(v(0, 0, u16::MAX).caret_upper_bound(), None);
+    }
+
+    #[test]
+    fn parse_tolerant_version() {
+        assert_eq!(Version::parse_tolerant("v1.2.3").unwrap(), version!(1:2:3));
+        assert_eq!(Version::parse_tolerant("V1.2.3").unwrap(), version!(1:2:3));
+        assert_eq!(Version::parse_tolerant("1.2.3").unwrap(), version!(1:2:3));
+        assert_eq!(
+            Version::parse_tolerant("v0.1.0-beta.5+build.1").unwrap(),
+            version!(0:1:0-beta:5+"build.1")
+        );
+        assert!(Version::parse("v1.2.3").is_none());
+        assert!(Version::parse_tolerant("version1.2.3").is_none());
+        assert!(Version::parse_tolerant("vv1.2.3").is_none());
+    }
+
+    #[test]
+    fn normalize_name() {
+        assert_eq!(
+            normalize_crate_name("Foo_Bar"),
+            normalize_crate_name("foo-bar")
+        );
+        assert_eq!(normalize_crate_name("foo-bar"), "foo_bar");
+        assert_ne!(
+            normalize_crate_name("foobar"),
+            normalize_crate_name("foo-bar")
+        );
+    }
+
+    #[test]
+    fn cache_file_name() {
+        let id = CrateId {
+            name: "foo",
+            version: version!(1:0:0+"build.1"),
+        };
+        assert_eq!(id.cache_file_name(), "foo-1.0.0.crate");
+
+        let id = CrateId {
+            name: "foo-bar",
+            version: version!(0:1:0-beta:2+"build.1"),
+        };
+        assert_eq!(id.cache_file_name(), "foo-bar-0.1.0-beta.2.crate");
+    }
+
+    #[test]
+    fn parse_file_name() {
+        let id = CrateId::parse_filename("foo.bar-1.0.0.crate").unwrap();
+        assert_eq!(id.name, "foo.bar");
+        assert_eq!(id.version, version!(1:0:0));
+
+        let id = CrateId::parse_filename("foo-bar-0.1.0-beta.2.crate").unwrap();
+        assert_eq!(id.name, "foo-bar");
+        assert_eq!(id.version, version!(0:1:0-beta:2));
+
+        assert!(CrateId::parse_filename("foo-1.0.0").is_none());
+        assert!(CrateId::parse_filename("foo-1.0.0.crate.tmp").is_none());
+        assert!(CrateId::parse_filename(".crate").is_none());
+    }
+
+    #[test]
+    fn canonicalize_version() {
+        for (input, expected) in [
+            ("1.2.3", "1.2.3"),
+            ("01.002.0003", "1.2.3"),
+            ("1.0.0-beta.01", "1.0.0-beta.1"),
+            ("0.1.00-rc.5+build.01", "0.1.0-rc.5"),
+        ] {
+            let canonical = Version::parse(input).unwrap().canonicalize();
+            assert_eq!(canonical, expected);
+            assert_eq!(
+                Version::parse(&canonical).unwrap().canonicalize(),
+                canonical
+            );
+        }
+        assert_eq!(
+            Version::parse_tolerant("v01.2.3").unwrap().canonicalize(),
+            Version::parse("1.2.3+build.5").unwrap().canonicalize(),
+        );
+    }
+
     #[test]
     fn latest_versions() {
         let mut versions = LatestVersions::default();
@@ -447,4 +652,52 @@ mod test {
             [version!(1:2:0), version!(1:3:0-rc:1)].as_slice()
         );
     }
+
+    #[test]
+    fn prerelease_only() {
+        let mut versions = LatestVersions::default();
+        assert!(!versions.has_only_prereleases());
+        assert!(!versions.stable_is_latest());
+
+        versions.push(version!(1:0:0-beta:1));
+        assert!(versions.has_only_prereleases());
+        assert!(!versions.stable_is_latest());
+
+        versions.push(version!(0:9:0));
+        assert!(!versions.has_only_prereleases());
+        assert!(!versions.stable_is_latest());
+
+        versions.push(version!(1:0:0));
+        assert!(!versions.has_only_prereleases());
+        assert!(versions.stable_is_latest());
+    }
+
+    #[test]
+    fn merge_latest_versions() {
+        let mut versions = LatestVersions::default();
+        versions.push(version!(1:0:0));
+        versions.push(version!(1:1:0-beta:1));
+
+        let mut other = LatestVersions::default();
+        other.push(version!(0:9:0));
+        other.push(version!(1:1:0-rc:2));
+        versions.merge(&other);
+        assert_eq!(
+            versions.iter_ids("").map(|x| x.version).collect::<Vec<_>>(),
+            [
+                version!(1:0:0),
+                version!(1:1:0-beta:1),
+                version!(1:1:0-rc:2),
+            ]
+            .as_slice()
+        );
+
+        let mut other = LatestVersions::default();
+        other.push(version!(1:1:0+"build.1"));
+        versions.merge(&other);
+        assert_eq!(
+            versions.iter_ids("").map(|x| x.version).collect::<Vec<_>>(),
+            [version!(1:1:0+"build.1")].as_slice()
+        );
+    }
 }
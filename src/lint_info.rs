@@ -0,0 +1,212 @@
+//! Lint metadata queried from `clippy-driver -W help` and clippy's source.
+
+use anyhow::{bail, Context, Result};
+use clippy_lint_test::{MainVersion, Version};
+use regex::Regex;
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+    str,
+};
+
+/// The default level, group and description of a lint.
+pub struct LintInfo {
+    pub default_level: String,
+    pub group: Option<String>,
+    pub description: String,
+}
+
+/// Runs `clippy-driver -W help` and collects the default level, group and description of every
+/// rustc and clippy lint.
+pub fn query(mut driver: Command) -> Result<HashMap<String, LintInfo>> {
+    let output = driver
+        .args(["-W", "help"])
+        .output()
+        .context("error running `clippy-driver`")?;
+    if !output.status.success() {
+        bail!(
+            "error running `clippy-driver` ({}):\n{}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    let stdout = str::from_utf8(&output.stdout)
+        .context("error converting `clippy-driver` output to `str`")?;
+    Ok(parse(stdout))
+}
+
+/// Parses the lint and lint group tables. Lints are listed as `name level description` and groups as
+/// `name member, member, ...`. Names are printed with dashes rather than underscores.
+fn parse(output: &str) -> HashMap<String, LintInfo> {
+    let mut lints = HashMap::new();
+    let mut groups = Vec::new();
+    for line in output.lines() {
+        let Some((name, rest)) = line.trim().split_once(char::is_whitespace) else {
+            continue;
+        };
+        if !name
+            .bytes()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || b"_-:".contains(&c))
+        {
+            continue;
+        }
+        let name = name.replace('-', "_");
+        let rest = rest.trim_start();
+        let (first, description) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        if matches!(first, "allow" | "warn" | "deny" | "forbid") {
+            lints.insert(
+                name,
+                LintInfo {
+                    default_level: first.into(),
+                    group: None,
+                    description: description.trim().into(),
+                },
+            );
+        } else if name != "clippy::all" && name != "warnings" {
+            groups.push((name, rest));
+        }
+    }
+    for (group, members) in groups {
+        let group = group.trim_start_matches("clippy::");
+        for member in members.split(',') {
+            if let Some(lint) = lints.get_mut(&member.trim().replace('-', "_")) {
+                lint.group = Some(group.into());
+            }
+        }
+    }
+    lints
+}
+
+/// Reads the version each lint was added in from the `#[clippy::version]` attributes in clippy's
+/// source. Lints added before versions were tracked are omitted.
+pub fn read_versions(clippy_dir: &Path) -> Result<HashMap<String, MainVersion>> {
+    let re = version_regex();
+    let mut versions = HashMap::new();
+    let mut dirs = vec![clippy_dir.join("clippy_lints").join("src")];
+    while let Some(dir) = dirs.pop() {
+        for entry in
+            fs::read_dir(&dir).with_context(|| format!("error reading dir `{}`", dir.display()))?
+        {
+            let path: PathBuf = entry
+                .with_context(|| format!("error reading dir `{}`", dir.display()))?
+                .path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path.extension().is_some_and(|ext| ext == "rs") {
+                let contents = fs::read_to_string(&path)
+                    .with_context(|| format!("error reading file `{}`", path.display()))?;
+                parse_versions(&re, &contents, &mut versions);
+            }
+        }
+    }
+    Ok(versions)
+}
+
+/// Matches a lint's version attribute followed by its name.
+fn version_regex() -> Regex {
+    Regex::new(r#"#\[clippy::version\s*=\s*"([^"]*)"\]\s*pub\s+([A-Z0-9_]+)\s*,"#).unwrap()
+}
+
+fn parse_versions(re: &Regex, contents: &str, versions: &mut HashMap<String, MainVersion>) {
+    for c in re.captures_iter(contents) {
+        if let Some(version) = Version::parse(&c[1]) {
+            versions.insert(
+                format!("clippy::{}", c[2].to_ascii_lowercase()),
+                version.main(),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse, parse_versions, version_regex};
+    use clippy_lint_test::MainVersion;
+    use std::collections::HashMap;
+
+    #[test]
+    fn parse_lint_help() {
+        let lints = parse(
+            "Lint checks provided by rustc:\n\
+            \n\
+            \x20                      name  default  meaning\n\
+            \x20                      ----  -------  -------\n\
+            \x20            unused-imports  warn     imports that are never used\n\
+            \n\
+            Lint groups provided by rustc:\n\
+            \n\
+            \x20              name  sub-lints\n\
+            \x20              ----  ---------\n\
+            \x20          warnings  all lints that are set to issue warnings\n\
+            \x20            unused  unused-imports, unused-variables\n\
+            \n\
+            Lint checks provided by plugins loaded by this crate:\n\
+            \n\
+            \x20                      name  default  meaning\n\
+            \x20                      ----  -------  -------\n\
+            \x20    clippy::absolute-paths  allow    checks for usage of an item without a `use`\n\
+            \x20   clippy::approx-constant  deny     the approximate of a known float constant\n\
+            \x20         clippy::let-unit-value  warn     creating a `let` binding to a value of unit type\n\
+            \n\
+            Lint groups provided by plugins loaded by this crate:\n\
+            \n\
+            \x20              name  sub-lints\n\
+            \x20              ----  ---------\n\
+            \x20       clippy::all  clippy::approx-constant, clippy::let-unit-value\n\
+            \x20   clippy::correctness  clippy::approx-constant\n\
+            \x20     clippy::style  clippy::let-unit-value\n\
+            \x20  clippy::restriction  clippy::absolute-paths\n",
+        );
+        assert_eq!(lints.len(), 4);
+        let info = &lints["unused_imports"];
+        assert_eq!(info.default_level, "warn");
+        assert_eq!(info.group.as_deref(), Some("unused"));
+        let info = &lints["clippy::approx_constant"];
+        assert_eq!(info.default_level, "deny");
+        assert_eq!(info.group.as_deref(), Some("correctness"));
+        assert_eq!(
+            info.description,
+            "the approximate of a known float constant"
+        );
+        let info = &lints["clippy::let_unit_value"];
+        assert_eq!(info.default_level, "warn");
+        assert_eq!(info.group.as_deref(), Some("style"));
+        let info = &lints["clippy::absolute_paths"];
+        assert_eq!(info.default_level, "allow");
+        assert_eq!(info.group.as_deref(), Some("restriction"));
+    }
+
+    #[test]
+    fn lint_versions() {
+        let re = version_regex();
+        let mut versions = HashMap::new();
+        parse_versions(
+            &re,
+            "declare_clippy_lint! {\n\
+            \x20   /// ### What it does\n\
+            \x20   #[clippy::version = \"1.75.0\"]\n\
+            \x20   pub NEW_LINT,\n\
+            \x20   style,\n\
+            \x20   \"default lint description\"\n\
+            }\n\
+            declare_clippy_lint! {\n\
+            \x20   #[clippy::version = \"pre 1.29.0\"]\n\
+            \x20   pub OLD_LINT,\n\
+            \x20   style,\n\
+            \x20   \"default lint description\"\n\
+            }\n",
+            &mut versions,
+        );
+        assert_eq!(versions.len(), 1);
+        assert_eq!(
+            versions["clippy::new_lint"],
+            MainVersion {
+                major: 1,
+                minor: 75,
+                patch: 0
+            }
+        );
+    }
+}
@@ -1,324 +1,2601 @@
 use anyhow::{bail, Context, Result};
-use cargo_metadata::{diagnostic::DiagnosticLevel, CompilerMessage, Message};
-use clippy_lint_test::{is_rustc_crate, CrateId, LatestVersions};
-use flate2::read::GzDecoder;
+use cargo_metadata::{
+    diagnostic::{Diagnostic, DiagnosticLevel},
+    CompilerMessage, Message, PackageId,
+};
+use chrono::format::{Item, StrftimeItems};
+use clippy_lint_test::{
+    cargo_command, dump::read_crates, fetch::Fetcher, init_cargo, is_rustc_crate,
+    normalize_crate_name, CrateId, LatestVersions, Version,
+};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
 use regex::{Regex, RegexBuilder};
 use rm_rf::remove;
 use std::{
-    collections::HashMap,
+    cell::RefCell,
+    cmp,
+    collections::{hash_map::Entry, HashMap, HashSet},
+    env,
     ffi::{OsStr, OsString},
     fmt, fs,
-    io::{self, Write},
-    path::{Path, PathBuf},
-    process::Command,
-    str,
+    io::{self, IsTerminal, Write},
+    iter, mem, ops,
+    path::{self, Component, Path, PathBuf},
+    process::{Command, Stdio},
+    str, thread,
+    time::{Duration, Instant},
 };
-use tar::Archive;
+use tar::{Archive, EntryType};
+
+mod baseline;
+mod blocklist;
+mod compare;
+mod lint_info;
+mod report;
+mod sarif;
+mod version_diff;
+
+use baseline::Baseline;
+use blocklist::Blocklist;
+use compare::{CrateDiff, DiffTotals};
+use lint_info::LintInfo;
+use report::{CrateReport, ReportFormat, Summary};
+use sarif::LintSpan;
 
 #[derive(argh::FromArgs)]
 /// Tests clippy lints on all downloaded crates
 struct Args {
-    /// clippy directory
+    /// clippy directory. The clippy installed by rustup is used if neither this nor
+    /// `--cargo-clippy` is given
     #[argh(positional)]
-    clippy_dir: PathBuf,
+    clippy_dir: Option<PathBuf>,
+
+    /// a second clippy directory to check each crate with. The report includes the differences
+    /// between the results of both
+    #[argh(option, long = "compare-clippy")]
+    compare_clippy: Option<PathBuf>,
+
+    /// use a prebuilt `cargo-clippy` binary rather than building clippy. `clippy-driver` must be
+    /// in the same directory unless `--clippy-driver` is given
+    #[argh(option, long = "cargo-clippy")]
+    cargo_clippy: Option<PathBuf>,
+
+    /// use a prebuilt `clippy-driver` binary rather than building clippy. `cargo-clippy` must be
+    /// in the same directory unless `--cargo-clippy` is given. Note `cargo-clippy` always checks
+    /// crates with the `clippy-driver` next to it; this one is used to query clippy's lints
+    #[argh(option, long = "clippy-driver")]
+    clippy_driver: Option<PathBuf>,
+
+    /// the `cargo` binary to use (default `$CARGO`, or `cargo` from `PATH`). The clippy toolchain
+    /// is selected with `RUSTUP_TOOLCHAIN` rather than `+TOOLCHAIN` when it's set
+    #[argh(option, long = "cargo")]
+    cargo: Option<PathBuf>,
 
-    /// the name of the report file (default `CLIPPY_BRANCH_NAME-CURRENT_DATE.txt`)
+    /// print why the given crate (e.g. `serde-1.0.0`) would be skipped when checking the cached
+    /// crates, then exit
+    #[argh(option, long = "explain-skip")]
+    explain_skip: Option<String>,
+
+    /// print clippy's lints with their group and default level, then exit
+    #[argh(switch, long = "list-lints")]
+    list_lints: bool,
+
+    /// the name of the report file (default `CLIPPY_BRANCH_NAME-CURRENT_TIME.txt`). If this is a
+    /// directory the default name is used within it
     #[argh(option, long = "report-file")]
     report_name: Option<PathBuf>,
 
-    /// lints to test
+    /// the `strftime` format of the time in the default report name (default `%Y-%m-%dT%H-%M`)
+    #[argh(option, long = "timestamp-format")]
+    timestamp_format: Option<String>,
+
+    /// use UTC rather than the local time in the default report name
+    #[argh(switch, long = "utc")]
+    utc: bool,
+
+    /// the format of the report file. One of `text`, `json`, `markdown` or `ndjson` (default
+    /// `text`)
+    #[argh(option, long = "report-format", default = "ReportFormat::Text")]
+    report_format: ReportFormat,
+
+    /// gzip the report file. `.gz` is appended to the default report name
+    #[argh(switch, long = "compress")]
+    compress: bool,
+
+    /// also write the location of every lint occurrence to the given file as a SARIF log
+    #[argh(option, long = "sarif")]
+    sarif: Option<PathBuf>,
+
+    /// lints to test. The level can be given as `LINT=LEVEL` where the level is one of `warn`,
+    /// `deny` or `forbid` (default `warn`). `*` and `?` can be used to match multiple lints. Names
+    /// of rustc lints are used as is, all others are assumed to be clippy lints
     #[argh(option, short = 'l', long = "lint")]
     lints: Vec<String>,
 
+    /// additional lints to allow while checking
+    #[argh(option, long = "allow")]
+    allow: Vec<String>,
+
+    /// test all lints added in or after the given clippy version, e.g. `1.75.0`. Requires a clippy
+    /// directory
+    #[argh(option, long = "lint-since")]
+    lint_since: Option<String>,
+
+    /// file containing the lints to test, one per line
+    #[argh(option, long = "lints-from")]
+    lints_from: Option<PathBuf>,
+
+    /// a previous JSON report to compare the number of crates each lint fires on against. Exits
+    /// with an error if any lint regresses
+    #[argh(option, long = "baseline")]
+    baseline: Option<PathBuf>,
+
+    /// the number of additional crates a lint may fire on compared to the baseline (default 0)
+    #[argh(option, long = "max-regression")]
+    max_regression: Option<usize>,
+
     /// regex filter of which messages to accept
     #[argh(option, long = "filter")]
     filter: Option<String>,
 
-    /// the number of crates to compile before clearing the target directory (default 500)
+    /// shell command used to filter which messages to accept. Each message is passed to the
+    /// command on stdin and is accepted if the command exits successfully
+    #[argh(option, long = "filter-command")]
+    filter_command: Option<String>,
+
+    /// the number of crates to compile before clearing the target directory, or the maximum size
+    /// of the target directory if a size suffix is given, e.g. `10GB` (default 500)
     #[argh(option, long = "cache-size")]
-    cache_size: Option<usize>,
+    cache_size: Option<CacheSize>,
+
+    /// the number of the most frequently used dependencies to keep when clearing the target
+    /// directory (default 0)
+    #[argh(option, long = "shared-deps")]
+    shared_deps: Option<usize>,
+
+    /// stop checking crates once this many lint occurrences have been found
+    #[argh(option, long = "stop-after")]
+    stop_after: Option<usize>,
+
+    /// the maximum number of messages to write to the report for each lint. Further occurrences
+    /// are still counted (default unlimited)
+    #[argh(option, long = "max-per-lint")]
+    max_per_lint: Option<usize>,
+
+    /// list the crates which weren't found in the crate cache at the end of the run. Only their
+    /// number is printed otherwise
+    #[argh(switch, long = "warn-on-missing-cache")]
+    warn_on_missing_cache: bool,
+
+    /// list up to this many distinct suggested replacements for each lint in the report summary
+    #[argh(option, long = "suggestion-samples")]
+    suggestion_samples: Option<usize>,
+
+    /// the maximum number of versions to check for each cached crate. The stable version is
+    /// preferred over pre-releases (default unlimited)
+    #[argh(option, long = "max-versions-per-crate")]
+    max_versions_per_crate: Option<usize>,
+
+    /// only check the stable version of each cached crate
+    #[argh(switch, long = "stable-only")]
+    stable_only: bool,
+
+    /// only check the pre-release versions of each cached crate
+    #[argh(switch, long = "prerelease-only")]
+    prerelease_only: bool,
+
+    /// stop at the first crate which causes an ICE
+    #[argh(switch, long = "fail-fast")]
+    fail_fast: bool,
+
+    /// check crates which failed to build a second time once all other crates are checked
+    #[argh(switch, long = "retry-failed")]
+    retry_failed: bool,
+
+    /// print the number of crates to check, the total size of their `.crate` files and an estimate
+    /// of how long checking them will take, then exit without checking any
+    #[argh(switch, long = "estimate")]
+    estimate: bool,
+
+    /// the average number of seconds to check a single crate used by `--estimate` (default 10)
+    #[argh(option, long = "secs-per-crate")]
+    secs_per_crate: Option<f64>,
+
+    /// check crates listed in the build blocklist next to the report. Crates which fail to build
+    /// are added to the blocklist and skipped by later runs unless this is given
+    #[argh(switch, long = "retest-failed")]
+    retest_failed: bool,
 
     /// checks if `clippy --fix` would succeed
     #[argh(switch, long = "fix")]
     fix: bool,
+
+    /// an environment variable to set when checking crates, e.g. `--env PROTOC=/usr/bin/protoc`
+    #[argh(option, long = "env")]
+    env: Vec<EnvVar>,
+
+    /// skip crates whose source is identical to a crate already checked in this run
+    #[argh(switch, long = "dedup-content")]
+    dedup_content: bool,
+
+    /// the targets to check as a comma separated list of `lib`, `bins`, `tests`, `benches` and
+    /// `examples` (default `lib,bins`)
+    #[argh(option, long = "targets", default = "Targets::default()")]
+    targets: Targets,
+
+    /// the number of jobs cargo runs in parallel while checking a single crate (default is the
+    /// number of CPUs)
+    #[argh(option, long = "cargo-jobs")]
+    cargo_jobs: Option<usize>,
+
+    /// the cargo profile to check crates with (default `dev`). Custom profiles need to be defined
+    /// for every crate, e.g. with `--env CARGO_PROFILE_NAME_INHERITS=dev`
+    #[argh(option, long = "profile")]
+    profile: Option<String>,
+
+    /// compile each crate incrementally, keeping its incremental data until the end of the run.
+    /// This speeds up checking the same crate again with `--compare-clippy` or `--retry-failed`
+    #[argh(switch, long = "incremental")]
+    incremental: bool,
+
+    /// limit the address space of cargo and every process it starts to this many megabytes.
+    /// Crates which run out of memory are skipped. Only supported on Unix
+    #[argh(option, long = "max-memory")]
+    max_memory: Option<u64>,
+
+    /// directory to extract crates and build them in (default is the system temp dir)
+    #[argh(option, long = "work-dir")]
+    work_dir: Option<PathBuf>,
+
+    /// copy the source of any crate which causes an ICE into this directory
+    #[argh(option, long = "keep-sources")]
+    keep_sources: Option<PathBuf>,
+
+    /// build against the crate's published `Cargo.lock`, if it has one, rather than the newest
+    /// compatible dependencies. Pinned dependencies may be too old to build with the current
+    /// toolchain
+    #[argh(switch, long = "keep-lockfile")]
+    keep_lockfile: bool,
+
+    /// record the version of every dependency each crate was built with in the JSON report
+    #[argh(switch, long = "record-deps")]
+    record_deps: bool,
+
+    /// a path, relative to the crate's root, to keep rather than removing before checking. By
+    /// default `.cargo/config`, `Cargo.lock` and the `tests` and `benches` directories are
+    /// removed. The directories are kept anyway if their targets are checked or they contain the
+    /// library or a binary
+    #[argh(option, long = "keep")]
+    keep: Vec<PathBuf>,
+
+    /// check crates whose only code is a build script. These are skipped by default since
+    /// everything under `src` is empty apart from comments and attributes
+    #[argh(switch, long = "check-build-script-only")]
+    check_build_script_only: bool,
+
+    /// disable the default features of the checked crate's dependencies. Crates which fail to
+    /// build afterwards are recorded as skipped
+    #[argh(switch, long = "minimize-dep-features")]
+    minimize_dep_features: bool,
+
+    /// path to a crates.io data dump used to annotate the report with download counts and sort
+    /// the summary by them
+    #[argh(option, long = "top-downloads")]
+    top_downloads: Option<PathBuf>,
+
+    /// the order to check the cached crates in. One of `name`, `downloads` or `size`, where
+    /// `downloads` and `size` check the largest first (default `name`)
+    #[argh(option, long = "sort-crates", default = "CrateOrder::Name")]
+    sort_crates: CrateOrder,
+
+    /// read the crate ids to check from stdin rather than checking all cached crates
+    #[argh(switch, long = "stdin")]
+    stdin: bool,
+
+    /// only check crates which weren't checked or skipped by a previous JSON report
+    #[argh(option, long = "since-report")]
+    since_report: Option<PathBuf>,
+
+    /// only check the crates which caused an ICE in a previous JSON report
+    #[argh(option, long = "ice-from")]
+    ice_from: Option<PathBuf>,
+
+    /// the directory containing the downloaded `.crate` files (default is all of cargo's registry
+    /// caches)
+    #[argh(option, long = "registry-cache")]
+    registry_cache: Option<PathBuf>,
+
+    /// check only the given crate (e.g. `serde-1.0.0`) and write the results to stdout. The crate
+    /// will be downloaded if needed
+    #[argh(option, long = "crate")]
+    krate: Option<String>,
+
+    /// check the crate in the given directory rather than the cached crates
+    #[argh(option, long = "path")]
+    path: Option<PathBuf>,
+
+    /// check the crate in the given git repository rather than the cached crates
+    #[argh(option, long = "git")]
+    git: Option<String>,
+
+    /// don't print the progress of each crate
+    #[argh(switch, short = 'q', long = "quiet")]
+    quiet: bool,
+
+    /// only print the number of crates checked
+    #[argh(switch, long = "progress-only")]
+    progress_only: bool,
+
+    /// when to color the progress of each crate. One of `auto`, `always` or `never` (default
+    /// `auto`)
+    #[argh(option, long = "color", default = "ColorChoice::Auto")]
+    color: ColorChoice,
+
+    /// open the report once complete using `$EDITOR`, or the system's default program
+    #[argh(switch, long = "open")]
+    open: bool,
+
+    /// show the full build log if clippy fails to compile
+    #[argh(switch, long = "show-full-build-log")]
+    show_full_build_log: bool,
 }
 
 fn main() -> Result<()> {
+    let start_time = Instant::now();
     let args: Args = argh::from_env();
+    init_cargo(args.cargo.as_deref());
     if args.filter.is_some() && args.fix {
         bail!("`--filter` and `--fix` can't be used together");
     }
+    if args.filter_command.is_some() && args.fix {
+        bail!("`--filter-command` and `--fix` can't be used together");
+    }
+    if args.compare_clippy.is_some() && args.fix {
+        bail!("`--compare-clippy` and `--fix` can't be used together");
+    }
+    if let Some(profile) = &args.profile {
+        check_profile_name(profile)?;
+    }
+    if args.stable_only && args.prerelease_only {
+        bail!("`--stable-only` and `--prerelease-only` can't be used together");
+    }
+    if cfg!(not(unix)) && args.max_memory.is_some() {
+        bail!("`--max-memory` is only supported on Unix");
+    }
+    if [
+        args.stdin,
+        args.path.is_some(),
+        args.git.is_some(),
+        args.krate.is_some(),
+        args.ice_from.is_some(),
+    ]
+    .into_iter()
+    .filter(|&x| x)
+    .count()
+        > 1
+    {
+        bail!("only one of `--stdin`, `--path`, `--git`, `--crate` and `--ice-from` can be used");
+    }
+
+    if args.list_lints {
+        return list_lints(&build_clippy(&args)?);
+    }
 
     let filter = args
         .filter
+        .as_ref()
         .map(|f| {
-            RegexBuilder::new(&f)
+            RegexBuilder::new(f)
                 .build()
                 .with_context(|| format!("error parsing `{}`", f))
         })
         .transpose()?;
-    let cache_size = args.cache_size.unwrap_or(500);
-
-    println!("Compiling clippy...");
-    let clippy_args = compile_clippy(&args.clippy_dir)?;
-
-    let mut report = io::BufWriter::new(
-        fs::OpenOptions::new()
-            .write(true)
-            .create(true)
-            .truncate(true)
-            .open(args.report_name.unwrap_or_else(|| {
-                let res = Command::new("git")
-                    .args(["branch", "--show-current"])
-                    .current_dir(&args.clippy_dir)
-                    .output();
-                let name = res.map_or(None, |res| {
-                    res.status
-                        .success()
-                        .then(|| ())
-                        .and_then(|()| String::from_utf8(res.stdout).ok())
-                });
-                let date = chrono::Local::today().format("%Y-%m-%d");
-                if let Some(name) = name {
-                    format!("{}-{}.txt", name.trim(), date)
-                } else {
-                    format!("{}.txt", date)
-                }
-                .into()
-            }))
-            .context("error creating report file")?,
-    );
+    let timestamp_format = args.timestamp_format.as_deref().unwrap_or("%Y-%m-%dT%H-%M");
+    if StrftimeItems::new(timestamp_format).any(|item| item == Item::Error) {
+        bail!("invalid timestamp format `{}`", timestamp_format);
+    }
+    let cache_size = args.cache_size.unwrap_or(CacheSize::Crates(500));
+    let work_dir = args.work_dir.as_deref().map(create_work_dir).transpose()?;
+    let _remove_work_dir = work_dir.as_deref().map(RemoveOnDrop);
+    if args.sort_crates == CrateOrder::Downloads && args.top_downloads.is_none() {
+        bail!("`--sort-crates downloads` requires `--top-downloads`");
+    }
 
-    let mut lint_counters = args
-        .lints
-        .into_iter()
-        .map(|name| {
-            let name = name.replace('-', "_");
-            let name = if !name.starts_with("clippy::") {
+    let cache_root = match &args.registry_cache {
+        Some(dir) => dir.clone(),
+        None => home::cargo_home()
+            .context("error finding cargo home dir")?
+            .join("registry")
+            .join("cache"),
+    };
+    let mut crates_dirs = find_cache_dirs(&cache_root, args.registry_cache.is_some())?;
+    if let Some(krate) = &args.explain_skip {
+        return explain_skip(&args, timestamp_format, &crates_dirs, krate);
+    }
+    if let Some(krate) = &args.krate {
+        let id =
+            CrateId::parse(krate).with_context(|| format!("error parsing crate id `{}`", krate))?;
+        let file_name = id.cache_file_name();
+        if !crates_dirs.iter().any(|dir| dir.join(&file_name).exists()) {
+            println!("Fetching `{}`...", krate);
+            if !Fetcher::new(true)?.fetch(&id)? {
+                bail!("error fetching `{}`", krate);
+            }
+            if let Some(dir) = &args.registry_cache {
+                // Cargo always downloads into its own cache.
+                let cargo_cache = home::cargo_home()
+                    .context("error finding cargo home dir")?
+                    .join("registry")
+                    .join("cache");
+                let file = find_crate_file(&find_cache_dirs(&cargo_cache, false)?, krate)
+                    .with_context(|| format!("`{}` not found after fetching", file_name))?;
+                fs::create_dir_all(dir)
+                    .with_context(|| format!("error creating dir `{}`", dir.display()))?;
+                fs::copy(&file, dir.join(&file_name))
+                    .with_context(|| format!("error copying file `{}`", file.display()))?;
+            }
+            // The fetch may have created a new cache directory.
+            crates_dirs = find_cache_dirs(&cache_root, args.registry_cache.is_some())?;
+        }
+    }
+    let source = if let Some(path) = &args.path {
+        CrateSource::Path(path)
+    } else if let Some(url) = &args.git {
+        CrateSource::Git(url)
+    } else {
+        if crates_dirs.is_empty() {
+            bail!(
+                "the crate cache directory `{}` doesn't exist\n\
+                run `cargo run --bin download_crates` to download crates first, or use \
+                `--registry-cache` to specify the directory containing the `.crate` files",
+                cache_root.display()
+            );
+        }
+        CrateSource::Cache(&crates_dirs)
+    };
+
+    let mut lints = args.lints.clone();
+    if let Some(path) = &args.lints_from {
+        lints.extend(read_lint_list(path)?);
+    }
+    if let Some(since) = &args.lint_since {
+        let since = Version::parse_tolerant(since)
+            .with_context(|| format!("error parsing version `{}`", since))?
+            .main();
+        let Some(clippy_dir) = &args.clippy_dir else {
+            bail!("`--lint-since` requires a clippy directory");
+        };
+        let mut new_lints: Vec<_> = lint_info::read_versions(clippy_dir)?
+            .into_iter()
+            .filter(|&(_, version)| version >= since)
+            .map(|(lint, _)| lint)
+            .collect();
+        if new_lints.is_empty() {
+            bail!("no lints were added since `{}`", since);
+        }
+        new_lints.sort();
+        // Explicitly listed lints take precedence so their level is kept.
+        new_lints.append(&mut lints);
+        lints = new_lints;
+    }
+    let mut lint_levels = HashMap::new();
+    let mut lint_patterns = Vec::new();
+    for lint in lints {
+        let (name, level) = match lint.split_once('=') {
+            Some((name, level)) => (name, level.parse().map_err(anyhow::Error::msg)?),
+            None => (lint.as_str(), LintLevel::Warn),
+        };
+        let name = name.trim().replace('-', "_");
+        if name.contains(['*', '?']) {
+            let name = if !name.contains("::") {
                 format!("clippy::{}", name)
             } else {
                 name
             };
-            (name, 0usize)
+            lint_patterns.push((name, level));
+        } else {
+            // The `clippy::` prefix is added once it's known whether this is a rustc lint.
+            lint_levels.insert(name, level);
+        }
+    }
+    if !args.fix && lint_levels.is_empty() && lint_patterns.is_empty() {
+        bail!("no lints specified; nothing to do");
+    }
+
+    // Checked before building clippy so a bad path doesn't waste the build.
+    let report_path = if args.krate.is_some() || args.estimate {
+        None
+    } else {
+        Some(report_path(&args, timestamp_format)?)
+    };
+
+    let downloads = args.top_downloads.as_deref().map(|p| {
+        read_crates(p)
+            .into_iter()
+            .map(|c| (c.name, c.download_count))
+            .collect::<HashMap<_, _>>()
+    });
+    let downloads_of = |krate: &str| {
+        let downloads = downloads.as_ref()?;
+        downloads.get(CrateId::parse(krate)?.name).copied()
+    };
+    let mut skipped = Vec::<(String, SkipReason)>::new();
+    let mut prerelease_only = HashSet::<String>::new();
+
+    let (crates, crate_count): (Box<dyn Iterator<Item = String>>, _) =
+        if let Some(path) = &args.path {
+            let path = path
+                .canonicalize()
+                .with_context(|| format!("error reading dir `{}`", path.display()))?;
+            let name = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .with_context(|| format!("error getting crate name from `{}`", path.display()))?;
+            (Box::new(iter::once(name.to_owned())), Some(1))
+        } else if let Some(url) = &args.git {
+            let name = url
+                .trim_end_matches('/')
+                .rsplit('/')
+                .next()
+                .map(|name| name.trim_end_matches(".git"))
+                .filter(|name| !name.is_empty())
+                .with_context(|| format!("error getting crate name from `{}`", url))?;
+            (Box::new(iter::once(name.to_owned())), Some(1))
+        } else if let Some(krate) = &args.krate {
+            (Box::new(iter::once(krate.clone())), Some(1))
+        } else if args.stdin {
+            let crates = io::stdin()
+                .lines()
+                .map_while(Result::ok)
+                .filter_map(|line| {
+                    let line = line.trim();
+                    if line.is_empty() {
+                        None
+                    } else if CrateId::parse(line).is_some() {
+                        Some(line.to_owned())
+                    } else {
+                        eprintln!("error parsing crate id `{}`", line);
+                        None
+                    }
+                });
+            (Box::new(crates), None)
+        } else if let Some(path) = &args.ice_from {
+            let crates = baseline::read_ice_crates(path)?;
+            let count = crates.len();
+            (Box::new(crates.into_iter()), Some(count))
+        } else {
+            let crates = find_crates(&crates_dirs, &mut skipped)?;
+            let mut crate_ids = Vec::with_capacity(crates.len() * 2);
+            for krate in crates {
+                let start = crate_ids.len();
+                let mut ids: Vec<_> = krate.iter_ids().collect();
+                filter_release_channel(&mut ids, &args);
+                if let Some(max) = args.max_versions_per_crate {
+                    limit_versions(&mut ids, max);
+                }
+                crate_ids.extend(ids.iter().map(|x| x.to_string()));
+                if krate.versions.has_only_prereleases() {
+                    prerelease_only.extend(crate_ids[start..].iter().cloned());
+                }
+            }
+            crate_ids.sort();
+            match args.sort_crates {
+                CrateOrder::Name => (),
+                CrateOrder::Downloads => {
+                    crate_ids.sort_by_cached_key(|krate| cmp::Reverse(downloads_of(krate)));
+                }
+                CrateOrder::Size => crate_ids
+                    .sort_by_cached_key(|krate| cmp::Reverse(crate_file_size(&crates_dirs, krate))),
+            }
+            let count = crate_ids.len();
+            (Box::new(crate_ids.into_iter()), Some(count))
+        };
+    let done = args
+        .since_report
+        .as_deref()
+        .map(baseline::read_done_crates)
+        .transpose()?;
+    let (crates, mut crate_count): (Box<dyn Iterator<Item = String>>, _) = match done {
+        Some(done) if crate_count.is_some() => {
+            let crates: Vec<_> = crates.filter(|krate| !done.contains(krate)).collect();
+            let count = crates.len();
+            (Box::new(crates.into_iter()), Some(count))
+        }
+        Some(done) => (
+            Box::new(crates.filter(move |krate| !done.contains(krate))),
+            None,
+        ),
+        None => (crates, crate_count),
+    };
+
+    if args.estimate {
+        let crates: Vec<_> = crates.collect();
+        print!(
+            "{}",
+            Estimate {
+                crate_count: crates.len(),
+                bytes: crates
+                    .iter()
+                    .map(|krate| crate_file_size(&crates_dirs, krate))
+                    .sum(),
+                secs_per_crate: args.secs_per_crate.unwrap_or(10.0),
+            }
+        );
+        return Ok(());
+    }
+
+    let clippy_args = build_clippy(&args)?;
+    let compare_clippy = args
+        .compare_clippy
+        .as_deref()
+        .map(|dir| {
+            eprintln!("Compiling clippy from `{}`...", dir.display());
+            compile_clippy(dir, args.show_full_build_log)
         })
+        .transpose()?;
+
+    let lint_info = match lint_info::query(clippy_args.driver_command()) {
+        Ok(info) => info,
+        Err(e) => {
+            eprintln!("warning: unable to get the default lint levels: {:#}", e);
+            HashMap::new()
+        }
+    };
+
+    let report_format = args.report_format;
+    let mut report = if let Some(report_path) = &report_path {
+        let file = io::BufWriter::new(
+            fs::OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(report_path)
+                .context("error creating report file")?,
+        );
+        if args.compress {
+            // Each flush ends a deflate block so the file can be read if the run is interrupted.
+            report_format.writer(GzEncoder::new(file, Compression::default()))
+        } else {
+            report_format.writer(file)
+        }
+    } else {
+        report_format.writer(io::stdout())
+    };
+
+    let baseline = args.baseline.as_deref().map(Baseline::read).transpose()?;
+    let mut blocklist = report_path
+        .as_deref()
+        .map(|p| Blocklist::read(blocklist_path(p)))
+        .transpose()?;
+
+    let mut lint_levels: HashMap<_, _> = lint_levels
+        .into_iter()
+        .map(|(name, level)| (qualify_lint_name(name, &lint_info), level))
+        .collect();
+    if !lint_patterns.is_empty() {
+        if lint_info.is_empty() {
+            bail!("lint patterns can't be used without clippy's lint list");
+        }
+        for (pattern, level) in &lint_patterns {
+            let re = glob_regex(pattern);
+            let mut found = false;
+            for lint in lint_info.keys().filter(|lint| re.is_match(lint)) {
+                found = true;
+                // Explicitly listed lints take precedence.
+                lint_levels.entry(lint.clone()).or_insert(*level);
+            }
+            if !found {
+                eprintln!("warning: no lints match `{}`", pattern);
+            }
+        }
+    }
+    if !lint_info.is_empty() {
+        let mut unknown: Vec<_> = lint_levels
+            .keys()
+            .filter(|&lint| !lint_info.contains_key(lint))
+            .collect();
+        unknown.sort();
+        for lint in &unknown {
+            eprintln!("warning: unknown lint `{}`", lint);
+        }
+        if unknown.len() == lint_levels.len() {
+            bail!("none of the specified lints exist; nothing to do");
+        }
+    }
+    let mut lint_counters = lint_levels
+        .keys()
+        .map(|name| (name.clone(), 0usize))
         .collect::<HashMap<_, _>>();
-    let mut per_crate_count = HashMap::<&str, CrateStatus>::new();
-
-    let home_dir = home::cargo_home().context("error finding cargo home dir")?;
-    let crates_dir = home_dir
-        .join("registry")
-        .join("cache")
-        .join("github.com-1ecc6299db9ec823");
-    let crates = find_crates(&crates_dir)?;
-    let mut crate_ids = Vec::with_capacity(crates.len() * 2);
-    for (name, versions) in crates {
-        crate_ids.extend(versions.iter_ids(&name).map(|x| x.to_string()));
-    }
-    let crates = crate_ids;
-
-    let temp_dir = temp_dir::TempDir::new().expect("error creating temp dir");
-    let temp_dir = temp_dir.path();
+    let mut per_crate_count = HashMap::<String, CrateStatus>::new();
+    let mut checked = HashSet::<String>::new();
+    let mut lint_crates = HashMap::<String, HashSet<String>>::new();
+    let mut fix_churn = HashMap::<String, Churn>::new();
+    let mut histograms = HashMap::<String, Histogram>::new();
+    let mut crate_lints = HashMap::<String, HashMap<String, usize>>::new();
+    let content_hashes = args
+        .dedup_content
+        .then(|| RefCell::new(HashMap::<u64, String>::new()));
+    let mut added_rlib = Vec::new();
+    let mut shown_msgs = HashMap::<String, usize>::new();
+    let mut omitted_msgs = HashMap::<String, usize>::new();
+    let mut diff_totals = compare_clippy.is_some().then(DiffTotals::default);
+    let mut sarif_spans = Vec::<(String, LintSpan)>::new();
+    let mut suggestion_samples = HashMap::<String, SuggestionSamples>::new();
+
+    let system_temp_dir;
+    let temp_dir = match &work_dir {
+        Some(dir) => dir.as_path(),
+        None => {
+            system_temp_dir = temp_dir::TempDir::new().expect("error creating temp dir");
+            system_temp_dir.path()
+        }
+    };
     let target_dir = temp_dir.join("target");
+    let compare_target_dir = temp_dir.join("target-compare");
+    let incremental_dir = args.incremental.then(|| temp_dir.join("incremental"));
+    let compare_incremental_dir = args
+        .incremental
+        .then(|| temp_dir.join("incremental-compare"));
+    let shared_deps = args.shared_deps.unwrap_or(0);
+    let mut crate_times = Vec::new();
+    let mut error_signatures = ErrorSignatures::new();
+    let check_options = CheckOptions {
+        filter: MessageFilter {
+            regex: filter.as_ref(),
+            command: args.filter_command.as_deref(),
+        },
+        collect_suggestions: args.suggestion_samples.is_some(),
+        fix: args.fix,
+        allow: &args.allow,
+        minimize_dep_features: args.minimize_dep_features,
+        env: &args.env,
+        levels: &lint_levels,
+        targets: args.targets,
+        keep_lockfile: args.keep_lockfile,
+        record_deps: args.record_deps,
+        keep: &args.keep,
+        skip_build_script_only: !args.check_build_script_only,
+        cargo_jobs: args.cargo_jobs,
+        profile: args.profile.as_deref(),
+        incremental_dir: incremental_dir.as_deref(),
+        max_memory: args.max_memory,
+        keep_sources: args.keep_sources.as_deref(),
+        hash_content: matches!(
+            args.report_format,
+            ReportFormat::Json | ReportFormat::Ndjson
+        ),
+        content_hashes: content_hashes.as_ref(),
+        compare: compare_clippy
+            .as_ref()
+            .map(|clippy| (clippy, compare_target_dir.as_path())),
+        compare_incremental_dir: compare_incremental_dir.as_deref(),
+    };
+    let show_progress = !args.quiet;
+    let show_crates = !args.quiet && !args.progress_only;
+    let colors = Colors(args.color.enabled());
+    let mut dep_counts = HashMap::<String, usize>::new();
 
-    for (i, krate) in crates.iter().enumerate() {
-        if i % cache_size == 0 {
-            // Don't let the target directory get too big.
+    let mut crates = crates.enumerate();
+    let mut retry = Vec::new();
+    let mut retrying = false;
+    let mut stopped_on_ice = None;
+    loop {
+        let Some((i, krate)) = crates.next() else {
+            if retrying || retry.is_empty() {
+                break;
+            }
+            println!("\nRetrying {} failed crates...", retry.len());
+            retrying = true;
             let _ = remove(&target_dir);
+            let _ = remove(&compare_target_dir);
+            crate_count = Some(retry.len());
+            crates = (Box::new(mem::take(&mut retry).into_iter()) as Box<dyn Iterator<Item = _>>)
+                .enumerate();
+            continue;
+        };
+        if !args.retest_failed && blocklist.as_ref().is_some_and(|b| b.contains(&krate)) {
+            if show_crates {
+                println!(
+                    "Skipping crate `{}` which previously failed to build",
+                    krate
+                );
+            }
+            skipped.push((krate, SkipReason::KnownBuildFailure));
+            continue;
+        }
+        let clear = match cache_size {
+            CacheSize::Crates(count) => i % count == 0,
+            CacheSize::Bytes(max) => i % SIZE_CHECK_INTERVAL == 0 && dir_size(&target_dir) > max,
+        };
+        if clear {
+            // Don't let the target directory get too big.
+            let keep = most_used_deps(&dep_counts, shared_deps);
+            let profile_dir = profile_dir_name(args.profile.as_deref().unwrap_or("dev"));
+            clear_target_dir(&target_dir.join(profile_dir), &keep);
+            clear_target_dir(&compare_target_dir.join(profile_dir), &keep);
         }
 
-        println!("Checking crate `{}`...", krate);
-        print!("{}/{}\r", i + 1, crates.len());
-        let _ = io::stdout().flush();
-        match check_crate(
+        if show_crates {
+            println!("Checking crate `{}`...", krate);
+        }
+        if show_progress {
+            if let Some(count) = crate_count {
+                print!("{}/{}\r", i + 1, count);
+            } else {
+                print!("{}\r", i + 1);
+            }
+            let _ = io::stdout().flush();
+        }
+        let crate_start_time = Instant::now();
+        let result = check_crate(
             &clippy_args,
+            &check_options,
             &target_dir,
             &mut lint_counters,
-            &crates_dir,
-            krate,
-            filter.as_ref(),
-            args.fix,
+            source,
+            &krate,
             temp_dir,
-        ) {
+        );
+        let crate_time = crate_start_time.elapsed();
+        checked.insert(krate.clone());
+        // Crates stopped by a denied lint would fail the same way again.
+        let failed = match &result {
             Ok(output) => {
-                if !output.fix_msg.is_empty() {
-                    println!("Failed to apply fixes");
-                    write!(
-                        report,
-                        "{}: Failed to apply fixes\n\n{}\n",
-                        krate, output.fix_msg
+                output.skipped.is_none()
+                    && !output.err_msg.is_empty()
+                    && output.ice_msg.is_empty()
+                    && !output.denied
+            }
+            Err(_) => true,
+        };
+        if args.retry_failed && !retrying && failed {
+            // Forget the failed run so the retry is counted as the only run.
+            if let Ok(output) = &result {
+                for (lint, _) in &output.lint_msgs {
+                    *lint_counters.get_mut(lint).unwrap() -= 1;
+                }
+            }
+            if let Some(content_hashes) = &content_hashes {
+                content_hashes
+                    .borrow_mut()
+                    .retain(|_, original| *original != krate);
+            }
+            if show_crates {
+                println!(
+                    "{}",
+                    colors.paint(Color::Yellow, "Failed, retrying at the end")
+                );
+            }
+            retry.push(krate);
+            continue;
+        }
+        crate_times.push(crate_time);
+        if let (Some(blocklist), Ok(output)) = (&mut blocklist, &result) {
+            // Only failures caused by the crate itself are recorded.
+            if output.skipped.is_none() && output.ice_msg.is_empty() && !output.denied {
+                if output.err_msg.is_empty() {
+                    blocklist.remove(&krate);
+                } else {
+                    blocklist.insert(&krate)?;
+                }
+            }
+        }
+        match result {
+            Ok(mut output) => {
+                if let Some(reason) = output.skipped {
+                    skipped.push((krate.clone(), reason));
+                }
+                if output.added_rlib {
+                    added_rlib.push(krate.clone());
+                }
+                for dep in &output.artifacts {
+                    *dep_counts.entry(dep.clone()).or_default() += 1;
+                }
+                if let Some(max) = args.suggestion_samples {
+                    for (lint, replacement) in output.suggestions.drain(..) {
+                        suggestion_samples
+                            .entry(lint)
+                            .or_default()
+                            .add(replacement, max);
+                    }
+                }
+                if args.sarif.is_some() {
+                    sarif_spans.extend(
+                        output
+                            .lint_spans
+                            .drain(..)
+                            .map(|span| (krate.clone(), span)),
+                    );
+                }
+                if args.fix {
+                    for (lint, churn) in &output.churn {
+                        *fix_churn.entry(lint.clone()).or_default() += *churn;
+                    }
+                }
+                for (lint, _) in &output.lint_msgs {
+                    lint_crates
+                        .entry(lint.clone())
+                        .or_default()
+                        .insert(krate.clone());
+                }
+                // Group the messages by lint, keeping the diagnostic order within each lint.
+                output.lint_msgs.sort_by(|x, y| x.0.cmp(&y.0));
+                for group in output.lint_msgs.chunk_by(|x, y| x.0 == y.0) {
+                    histograms
+                        .entry(group[0].0.clone())
+                        .or_default()
+                        .add(group.len());
+                }
+                if output.skipped.is_none()
+                    && output.err_msg.is_empty()
+                    && output.ice_msg.is_empty()
+                {
+                    crate_lints.insert(
+                        krate.clone(),
+                        output
+                            .lint_msgs
+                            .chunk_by(|x, y| x.0 == y.0)
+                            .map(|group| (group[0].0.clone(), group.len()))
+                            .collect(),
+                    );
+                }
+                let diff = output.compared.as_mut().and_then(|compared| {
+                    compared.lint_msgs.sort_by(|x, y| x.0.cmp(&y.0));
+                    CrateDiff::new(
+                        &output.lint_msgs,
+                        !output.ice_msg.is_empty(),
+                        &compared.lint_msgs,
+                        !compared.ice_msg.is_empty(),
                     )
-                    .context("error writing report")?;
-                    report.flush().context("error writing report")?;
-                    per_crate_count.entry(krate).or_default().fix_failed = true;
+                });
+                if let (Some(totals), Some(diff)) = (&mut diff_totals, &diff) {
+                    totals.add(&krate, diff);
+                    if show_crates {
+                        let msg = format!(
+                            "{} new, {} fixed warnings compared to the other clippy",
+                            diff.added.len(),
+                            diff.removed.len()
+                        );
+                        println!("{}", colors.paint(Color::Yellow, msg));
+                    }
                 }
-                if !output.lint_msgs.is_empty() {
-                    println!("Found {} warnings", output.lint_msgs.len());
-                    write!(report, "{}: {} warnings\n\n", krate, output.lint_msgs.len())
+                let capped_msgs;
+                let lint_msgs = if let Some(max) = args.max_per_lint {
+                    capped_msgs = output
+                        .lint_msgs
+                        .iter()
+                        .filter(|(lint, _)| {
+                            let shown = shown_msgs.entry(lint.clone()).or_default();
+                            if *shown < max {
+                                *shown += 1;
+                                true
+                            } else {
+                                *omitted_msgs.entry(lint.clone()).or_default() += 1;
+                                false
+                            }
+                        })
+                        .cloned()
+                        .collect::<Vec<_>>();
+                    capped_msgs.as_slice()
+                } else {
+                    output.lint_msgs.as_slice()
+                };
+                let crate_report = CrateReport {
+                    name: &krate,
+                    downloads: downloads_of(&krate),
+                    fix_msg: (!output.fix_msg.is_empty()).then_some(output.fix_msg.as_str()),
+                    lint_msgs,
+                    ambiguous_dep: output.ambiguous_dep.as_deref(),
+                    err_msg: (!output.err_msg.is_empty()).then_some(output.err_msg.as_str()),
+                    ice_msg: (!output.ice_msg.is_empty()).then_some(output.ice_msg.as_str()),
+                    duplicate_of: output.duplicate_of.as_deref(),
+                    ice_repro: output.ice_repro.as_deref(),
+                    prerelease_only: prerelease_only.contains(&krate),
+                    content_hash: output.content_hash,
+                    resolved_deps: output.resolved_deps.as_deref(),
+                    categories: &output.categories,
+                    keywords: &output.keywords,
+                    diff: diff.as_ref(),
+                };
+                if crate_report.duplicate_of.is_some()
+                    || crate_report.diff.is_some()
+                    || crate_report.fix_msg.is_some()
+                    || !crate_report.lint_msgs.is_empty()
+                    || crate_report.ambiguous_dep.is_some()
+                    || crate_report.ice_msg.is_some()
+                {
+                    report
+                        .write_crate(&crate_report)
                         .context("error writing report")?;
-                    for m in &output.lint_msgs {
-                        report
-                            .write_all(m.as_bytes())
-                            .context("error writing report")?;
+                }
+
+                if show_crates
+                    && output.skipped.is_none()
+                    && output.err_msg.is_empty()
+                    && crate_report.fix_msg.is_none()
+                    && output.lint_msgs.is_empty()
+                    && crate_report.ambiguous_dep.is_none()
+                    && crate_report.ice_msg.is_none()
+                {
+                    println!("{}", colors.paint(Color::Green, "No warnings"));
+                }
+                if crate_report.fix_msg.is_some() {
+                    if show_crates {
+                        println!("{}", colors.paint(Color::Red, "Failed to apply fixes"));
+                    }
+                    per_crate_count.entry(krate.clone()).or_default().fix_failed = true;
+                }
+                if !output.lint_msgs.is_empty() {
+                    if show_crates {
+                        let msg = format!("Found {} warnings", output.lint_msgs.len());
+                        println!("{}", colors.paint(Color::Yellow, msg));
                     }
-                    writeln!(report).context("error writing report")?;
-                    report.flush().context("error writing report")?;
-                    per_crate_count.entry(krate).or_default().lint_count = output.lint_msgs.len();
+                    let status = per_crate_count.entry(krate.clone()).or_default();
+                    status.lint_count = output.lint_msgs.len();
+                    status.failed = !output.err_msg.is_empty();
+                }
+                if let Some(dep) = output.ambiguous_dep {
+                    if show_crates {
+                        let msg = format!("Ambiguous dependency `{}`", dep);
+                        println!("{}", colors.paint(Color::Yellow, msg));
+                    }
+                    per_crate_count
+                        .entry(krate.clone())
+                        .or_default()
+                        .ambiguous_dep = Some(dep);
                 }
                 if !output.ice_msg.is_empty() {
-                    println!();
-                    write!(report, "{}: ICE\n\n{}\n", krate, output.ice_msg)
-                        .context("error writing report")?;
-                    report.flush().context("error writing report")?;
-                    per_crate_count.entry(krate).or_default().ice = true;
+                    if show_crates {
+                        println!("{}", colors.paint(Color::Red, "Internal compiler error"));
+                    }
+                    per_crate_count.entry(krate.clone()).or_default().ice = true;
+                    if args.fail_fast {
+                        println!("\nInternal compiler error while checking `{}`", krate);
+                        if let Some(repro) = &output.ice_repro {
+                            print!("Reproduce with:\n\n{}", repro);
+                        }
+                        stopped_on_ice = Some(krate.clone());
+                    }
+                }
+                if output.denied {
+                    if show_crates {
+                        let msg = "Build stopped by a denied lint";
+                        println!("{}", colors.paint(Color::Yellow, msg));
+                    }
+                    per_crate_count.entry(krate.clone()).or_default().denied = true;
+                }
+                if output.incomplete && !output.lint_msgs.is_empty() {
+                    if show_crates {
+                        let msg = "Build didn't finish, warnings may be incomplete";
+                        println!("{}", colors.paint(Color::Yellow, msg));
+                    }
+                    per_crate_count.entry(krate.clone()).or_default().incomplete = true;
                 }
                 if !output.err_msg.is_empty() {
+                    error_signatures.add(&output.err_msg);
                     for line in output.err_msg.lines() {
                         if line.is_empty() {
                             println!();
                         } else {
-                            println!("  {}", line);
+                            println!("  {}", colors.paint(Color::Red, line));
                         }
                     }
                 }
             }
-            Err(e) => eprintln!("{}", e),
+            Err(e) => {
+                eprintln!("{}", e);
+                skipped.push((krate, SkipReason::Error));
+            }
+        }
+
+        if stopped_on_ice.is_some() {
+            break;
+        }
+        if let Some(stop_after) = args.stop_after {
+            let total = lint_counters.values().sum::<usize>();
+            if total >= stop_after {
+                println!("\nFound {} lint occurrences, stopping early", total);
+                break;
+            }
         }
     }
+    if let Some(blocklist) = &blocklist {
+        blocklist.save()?;
+    }
 
-    write!(report, "\nReport summary:\n\n").context("error writing report")?;
-    for (krate, status) in per_crate_count {
-        writeln!(report, "{}: {}", krate, status).context("error writing report")?
+    let mut per_crate_count: Vec<_> = per_crate_count
+        .into_iter()
+        .map(|(krate, status)| {
+            let downloads = downloads_of(&krate);
+            (krate, status, downloads)
+        })
+        .collect();
+    if downloads.is_some() {
+        per_crate_count.sort_by(|x, y| x.2.cmp(&y.2).reverse());
+    }
+    let common_errors = error_signatures.most_common(COMMON_ERROR_COUNT);
+    let timing = TimingSummary::new(start_time.elapsed(), &mut crate_times);
+    let version_diffs = version_diff::version_diffs(&crate_lints);
+    report
+        .write_summary(&Summary {
+            checked: &checked,
+            crates: &per_crate_count,
+            toolchain: clippy_args.toolchain(),
+            clippy_version: clippy_args.version(),
+            lints: &lint_counters,
+            lint_info: &lint_info,
+            histograms: &histograms,
+            fix_churn: args.fix.then_some(&fix_churn),
+            version_diffs: &version_diffs,
+            added_rlib: &added_rlib,
+            omitted: &omitted_msgs,
+            suggestion_samples: &suggestion_samples,
+            diff_totals: diff_totals.as_ref(),
+            skipped: &skipped,
+            common_errors: &common_errors,
+            timing: &timing,
+        })
+        .context("error writing report")?;
+    drop(report);
+    if let Some(path) = &args.sarif {
+        sarif::write(path, &lint_counters, &lint_info, &sarif_spans)?;
+    }
+
+    println!("\n\nSummary:");
+    println!(
+        "{} crates with results, {} ICEs, {} fix failures, {} skipped",
+        per_crate_count.len(),
+        per_crate_count.iter().filter(|(_, s, _)| s.ice).count(),
+        per_crate_count
+            .iter()
+            .filter(|(_, s, _)| s.fix_failed)
+            .count(),
+        skipped.len(),
+    );
+    let missing: Vec<_> = skipped
+        .iter()
+        .filter(|(_, reason)| matches!(reason, SkipReason::MissingFromCache))
+        .map(|(krate, _)| krate.as_str())
+        .collect();
+    if !missing.is_empty() {
+        let msg = format!("{} crates were missing from the crate cache", missing.len());
+        println!("{}", colors.paint(Color::Yellow, msg));
+        if args.warn_on_missing_cache {
+            for krate in missing {
+                println!("  {}", krate);
+            }
+        }
+    }
+    let mut lint_totals: Vec<_> = lint_counters.iter().collect();
+    lint_totals.sort_by(|x, y| x.1.cmp(y.1).reverse().then(x.0.cmp(y.0)));
+    for (lint, count) in lint_totals {
+        println!("{}: {} occurrences", lint, count);
     }
-    writeln!(report).context("error writing report")?;
-    for (lint, count) in lint_counters {
-        writeln!(report, "{}: {} occurrences", lint, count).context("error writing report")?;
+    if args.fix {
+        let total = fix_churn.values().fold(Churn::default(), |x, &y| x + y);
+        println!("Fixes would change {}", total);
+    }
+    print!("{}", timing);
+    if let Some(report_path) = &report_path {
+        println!("Report written to `{}`", report_path.display());
+        if args.open {
+            open_file(report_path)?;
+        }
     }
-    report.flush().context("error writing report")?;
 
     let _ = remove(&target_dir);
+    let _ = remove(&compare_target_dir);
+    for dir in incremental_dir.iter().chain(&compare_incremental_dir) {
+        let _ = remove(dir);
+    }
+
+    if let Some(krate) = stopped_on_ice {
+        bail!("stopped after an ICE in `{}`", krate);
+    }
+    if let Some(baseline) = &baseline {
+        let regressions = baseline.regressions(
+            &lint_crates,
+            &checked,
+            &skipped,
+            args.max_regression.unwrap_or(0),
+        );
+        if !regressions.is_empty() {
+            println!("\nLints exceeding the baseline:");
+            for regression in &regressions {
+                println!("{}", regression);
+            }
+            bail!("{} lints exceeded the baseline", regressions.len());
+        }
+    }
     Ok(())
 }
 
-#[derive(Default)]
-struct CrateStatus {
-    lint_count: usize,
-    ice: bool,
-    fix_failed: bool,
-}
-impl fmt::Display for CrateStatus {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "{}{}{} warning{}",
-            if self.ice { "ICE, " } else { "" },
-            if self.fix_failed { "Fix failed, " } else { "" },
-            self.lint_count,
-            if self.lint_count == 1 { "" } else { "s" },
-        )
+/// Opens a file using `$EDITOR` if set, or the system's default program.
+fn open_file(p: &Path) -> Result<()> {
+    let mut command = if let Some(editor) = env::var_os("EDITOR").filter(|x| !x.is_empty()) {
+        Command::new(editor)
+    } else if cfg!(target_os = "windows") {
+        let mut command = Command::new("cmd");
+        command.args(["/C", "start", ""]);
+        command
+    } else if cfg!(target_os = "macos") {
+        Command::new("open")
+    } else {
+        Command::new("xdg-open")
+    };
+    let status = command
+        .arg(p)
+        .status()
+        .with_context(|| format!("error opening `{}`", p.display()))?;
+    if !status.success() {
+        bail!("error opening `{}` ({})", p.display(), status);
     }
+    Ok(())
 }
 
-fn find_crates(p: &Path) -> Result<HashMap<String, LatestVersions>> {
-    let mut crates = HashMap::<_, LatestVersions>::new();
-    for file in fs::read_dir(p).with_context(|| format!("error reading dir `{}`", p.display()))? {
-        let file = file.with_context(|| format!("error reading dir `{}`", p.display()))?;
-        if let Some(id) = file
-            .path()
-            .file_stem()
-            .and_then(|name| CrateId::parse(name.to_str()?))
-        {
-            if is_rustc_crate(id.name) {
-                // Ignore rustc crates as they likely won't build.
-                continue;
-            }
-            crates.entry(id.name.into()).or_default().push(id.version);
+/// The number of error signatures listed in the report summary.
+const COMMON_ERROR_COUNT: usize = 20;
+
+/// Counts how many crates fail with each kind of error.
+struct ErrorSignatures {
+    /// Matches each token which might be a path. See `is_path_like`.
+    token: Regex,
+    version: Regex,
+    counts: HashMap<String, usize>,
+}
+impl ErrorSignatures {
+    fn new() -> Self {
+        Self {
+            token: Regex::new(r#"[^\s`'"]+"#).unwrap(),
+            version: Regex::new(r"\bv?\d+\.\d+\.\d+(?:-[0-9A-Za-z.]+)?(?:\+[0-9A-Za-z.]+)?\b")
+                .unwrap(),
+            counts: HashMap::new(),
+        }
+    }
+
+    /// Adds a crate's error message. Only the first error is used and any paths and version
+    /// numbers are removed.
+    fn add(&mut self, err_msg: &str) {
+        let line = err_msg
+            .lines()
+            .map(str::trim)
+            .find(|line| {
+                line.starts_with("error")
+                    && !line.starts_with("error running clippy")
+                    && !line.starts_with("error: could not compile")
+            })
+            .or_else(|| err_msg.lines().map(str::trim).find(|line| !line.is_empty()));
+        if let Some(line) = line {
+            let line = self.token.replace_all(line, |c: &regex::Captures<'_>| {
+                if is_path_like(&c[0]) {
+                    "<path>".into()
+                } else {
+                    c[0].to_owned()
+                }
+            });
+            let line = self.version.replace_all(&line, "<version>");
+            *self.counts.entry(line.into_owned()).or_default() += 1;
         }
     }
-    Ok(crates)
+
+    /// Gets the most common error signatures along with how many crates failed with them.
+    fn most_common(&self, count: usize) -> Vec<(&str, usize)> {
+        let mut errors: Vec<_> = self
+            .counts
+            .iter()
+            .map(|(msg, &count)| (msg.as_str(), count))
+            .collect();
+        errors.sort_unstable_by(|x, y| x.1.cmp(&y.1).reverse().then(x.0.cmp(y.0)));
+        errors.truncate(count);
+        errors
+    }
 }
 
-fn parse_toml(p: &Path) -> Result<toml::Value> {
-    fs::read_to_string(p)
-        .with_context(|| format!("error reading `{}`", p.display()))?
-        .parse()
-        .with_context(|| format!("error parsing file `{}`", p.display()))
+/// Checks if a token from an error message looks like a path. Only absolute paths and paths to
+/// source files are matched so prose like `and/or` is kept.
+fn is_path_like(token: &str) -> bool {
+    let bytes = token.as_bytes();
+    let is_drive = bytes.len() > 2
+        && bytes[0].is_ascii_alphabetic()
+        && bytes[1] == b':'
+        && matches!(bytes[2], b'\\' | b'/');
+    token.starts_with('/') || token.starts_with("src/") || token.contains(".rs") || is_drive
 }
 
-struct ClippyArgs {
-    manifest: OsString,
-    channel: String,
+/// The time taken for the whole run.
+struct TimingSummary {
+    total: Duration,
+    crate_count: usize,
+    /// The minimum, median and maximum time to check a single crate.
+    per_crate: Option<(Duration, Duration, Duration)>,
 }
-impl ClippyArgs {
+impl TimingSummary {
+    fn new(total: Duration, crate_times: &mut [Duration]) -> Self {
+        crate_times.sort_unstable();
+        Self {
+            total,
+            crate_count: crate_times.len(),
+            per_crate: crate_times.first().map(|&min| {
+                (
+                    min,
+                    crate_times[crate_times.len() / 2],
+                    crate_times[crate_times.len() - 1],
+                )
+            }),
+        }
+    }
+}
+impl fmt::Display for TimingSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Checked {} crates in {:.2?} ({:.2} crates/s)",
+            self.crate_count,
+            self.total,
+            self.crate_count as f64 / self.total.as_secs_f64(),
+        )?;
+        if let Some((min, median, max)) = self.per_crate {
+            writeln!(
+                f,
+                "Time per crate: min {:.2?}, median {:.2?}, max {:.2?}",
+                min, median, max
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// A rough estimate of the work needed to check a set of crates.
+struct Estimate {
+    crate_count: usize,
+    /// The total size of the `.crate` files to extract.
+    bytes: u64,
+    secs_per_crate: f64,
+}
+impl fmt::Display for Estimate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mins = (self.crate_count as f64 * self.secs_per_crate / 60.0).round() as u64;
+        writeln!(f, "Crates to check: {}", self.crate_count)?;
+        writeln!(
+            f,
+            "Total `.crate` size: {:.1} MB",
+            self.bytes as f64 / 1_000_000.0
+        )?;
+        writeln!(
+            f,
+            "Estimated time: {}h {:02}m at {}s per crate",
+            mins / 60,
+            mins % 60,
+            self.secs_per_crate
+        )
+    }
+}
+
+/// How many crates are checked between each check of the target directory's size.
+const SIZE_CHECK_INTERVAL: usize = 10;
+
+/// When to clear the target directory.
+#[derive(Clone, Copy)]
+enum CacheSize {
+    /// Clear after the given number of crates.
+    Crates(usize),
+    /// Clear once the target directory exceeds the given number of bytes.
+    Bytes(u64),
+}
+impl str::FromStr for CacheSize {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Ok(count) = s.parse() {
+            return if count == 0 {
+                Err("the cache size must be at least one crate".into())
+            } else {
+                Ok(Self::Crates(count))
+            };
+        }
+        let err = || format!("invalid cache size `{}`", s);
+        let (size, has_b) = match s.strip_suffix("iB").or_else(|| s.strip_suffix('B')) {
+            Some(size) => (size, true),
+            None => (s, false),
+        };
+        let (size, multiplier) = match size.char_indices().last() {
+            Some((i, 'K' | 'k')) => (&size[..i], 1u64 << 10),
+            Some((i, 'M' | 'm')) => (&size[..i], 1 << 20),
+            Some((i, 'G' | 'g')) => (&size[..i], 1 << 30),
+            Some((i, 'T' | 't')) => (&size[..i], 1 << 40),
+            // A byte size needs a suffix so it isn't mistaken for a crate count.
+            _ if has_b => (size, 1),
+            _ => return Err(err()),
+        };
+        let size: f64 = size.trim().parse().map_err(|_| err())?;
+        if !size.is_finite() || size < 0.0 {
+            return Err(err());
+        }
+        Ok(Self::Bytes((size * multiplier as f64) as u64))
+    }
+}
+
+/// The level to test a lint at.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum LintLevel {
+    Warn,
+    Deny,
+    Forbid,
+}
+impl LintLevel {
+    /// Gets the compiler flag which sets the level.
+    fn flag(self) -> &'static str {
+        match self {
+            Self::Warn => "--warn",
+            Self::Deny => "--deny",
+            Self::Forbid => "--forbid",
+        }
+    }
+}
+impl str::FromStr for LintLevel {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "warn" => Ok(Self::Warn),
+            "deny" => Ok(Self::Deny),
+            "forbid" => Ok(Self::Forbid),
+            _ => Err(format!(
+                "unknown lint level `{}`, expected one of `warn`, `deny` or `forbid`",
+                s
+            )),
+        }
+    }
+}
+
+/// The order to check crates in.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum CrateOrder {
+    Name,
+    /// Most downloaded first.
+    Downloads,
+    /// Largest `.crate` file first.
+    Size,
+}
+impl str::FromStr for CrateOrder {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "name" => Ok(Self::Name),
+            "downloads" => Ok(Self::Downloads),
+            "size" => Ok(Self::Size),
+            _ => Err(format!(
+                "unknown crate order `{}`, expected one of `name`, `downloads` or `size`",
+                s
+            )),
+        }
+    }
+}
+
+/// When to color the tool's own output.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ColorChoice {
+    /// Only when stdout is a terminal and `NO_COLOR` isn't set.
+    Auto,
+    Always,
+    Never,
+}
+impl str::FromStr for ColorChoice {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            "always" => Ok(Self::Always),
+            "never" => Ok(Self::Never),
+            _ => Err(format!(
+                "unknown color choice `{}`, expected one of `auto`, `always` or `never`",
+                s
+            )),
+        }
+    }
+}
+impl ColorChoice {
+    fn enabled(self) -> bool {
+        match self {
+            Self::Auto => {
+                env::var_os("NO_COLOR").is_none_or(|x| x.is_empty()) && io::stdout().is_terminal()
+            }
+            Self::Always => true,
+            Self::Never => false,
+        }
+    }
+}
+
+/// The colors used for status messages.
+#[derive(Clone, Copy)]
+enum Color {
+    Red = 31,
+    Green = 32,
+    Yellow = 33,
+}
+
+/// Colors status messages if enabled.
+#[derive(Clone, Copy)]
+struct Colors(bool);
+impl Colors {
+    fn paint<T: fmt::Display>(self, color: Color, text: T) -> Painted<T> {
+        Painted {
+            color: self.0.then_some(color),
+            text,
+        }
+    }
+}
+
+/// Text wrapped in the escape codes for a color.
+struct Painted<T> {
+    color: Option<Color>,
+    text: T,
+}
+impl<T: fmt::Display> fmt::Display for Painted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.color {
+            Some(color) => write!(f, "\x1b[{}m{}\x1b[0m", color as u8, self.text),
+            None => self.text.fmt(f),
+        }
+    }
+}
+
+/// The kinds of targets to check.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Targets {
+    lib: bool,
+    bins: bool,
+    tests: bool,
+    benches: bool,
+    examples: bool,
+}
+impl Default for Targets {
+    /// The targets `cargo check` uses by default.
+    fn default() -> Self {
+        Self {
+            lib: true,
+            bins: true,
+            tests: false,
+            benches: false,
+            examples: false,
+        }
+    }
+}
+impl str::FromStr for Targets {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut res = Self {
+            lib: false,
+            bins: false,
+            tests: false,
+            benches: false,
+            examples: false,
+        };
+        for target in s.split(',').map(str::trim) {
+            let field = match target {
+                "lib" => &mut res.lib,
+                "bins" => &mut res.bins,
+                "tests" => &mut res.tests,
+                "benches" => &mut res.benches,
+                "examples" => &mut res.examples,
+                _ => {
+                    return Err(format!(
+                        "unknown target `{}`, expected one of `lib`, `bins`, `tests`, `benches` or \
+                        `examples`",
+                        target
+                    ))
+                }
+            };
+            *field = true;
+        }
+        Ok(res)
+    }
+}
+
+/// An environment variable given as `KEY=VALUE`.
+struct EnvVar {
+    key: String,
+    value: String,
+}
+impl str::FromStr for EnvVar {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((key, value)) if !key.is_empty() => Ok(Self {
+                key: key.into(),
+                value: value.into(),
+            }),
+            _ => Err(format!(
+                "invalid environment variable `{}`, expected `KEY=VALUE`",
+                s
+            )),
+        }
+    }
+}
+
+/// Calculates the total size of all files in a directory.
+fn dir_size(p: &Path) -> u64 {
+    fs::read_dir(p).map_or(0, |entries| {
+        entries
+            .flatten()
+            .map(|entry| match entry.file_type() {
+                Ok(t) if t.is_dir() => dir_size(&entry.path()),
+                Ok(_) => entry.metadata().map_or(0, |m| m.len()),
+                Err(_) => 0,
+            })
+            .sum()
+    })
+}
+
+#[derive(Default)]
+struct CrateStatus {
+    lint_count: usize,
+    ice: bool,
+    /// A lint tested at the deny or forbid level stopped the build.
+    denied: bool,
+    /// The build stopped partway, so the warnings may be incomplete.
+    incomplete: bool,
+    /// The build failed with an error other than a tested lint.
+    failed: bool,
+    fix_failed: bool,
+    ambiguous_dep: Option<String>,
+}
+impl fmt::Display for CrateStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(dep) = &self.ambiguous_dep {
+            write!(f, "Ambiguous deps (`{}`), ", dep)?;
+        }
+        write!(
+            f,
+            "{}{}{}{}{}{} warning{}",
+            if self.ice { "ICE, " } else { "" },
+            if self.denied { "Denied, " } else { "" },
+            if self.incomplete { "Incomplete, " } else { "" },
+            if self.failed { "Build failed, " } else { "" },
+            if self.fix_failed { "Fix failed, " } else { "" },
+            self.lint_count,
+            if self.lint_count == 1 { "" } else { "s" },
+        )
+    }
+}
+
+/// The upper bound of each bucket of a histogram, except the last which is unbounded.
+const HISTOGRAM_BOUNDS: [usize; 3] = [1, 5, 20];
+
+/// The number of crates a lint fired on, bucketed by how many times it fired in each crate.
+#[derive(Default)]
+struct Histogram([usize; HISTOGRAM_BOUNDS.len() + 1]);
+impl Histogram {
+    fn add(&mut self, count: usize) {
+        let bucket = HISTOGRAM_BOUNDS.partition_point(|&bound| bound < count);
+        self.0[bucket] += 1;
+    }
+
+    /// Iterates over the label and count of each bucket, e.g. `("2-5", 10)`.
+    fn buckets(&self) -> impl Iterator<Item = (String, usize)> + '_ {
+        let mut lower = 1;
+        self.0.iter().enumerate().map(move |(i, &count)| {
+            let label = match HISTOGRAM_BOUNDS.get(i) {
+                Some(&upper) if upper == lower => upper.to_string(),
+                Some(&upper) => format!("{}-{}", lower, upper),
+                None => format!("{}+", lower),
+            };
+            lower = HISTOGRAM_BOUNDS.get(i).map_or(lower, |&upper| upper + 1);
+            (label, count)
+        })
+    }
+}
+
+/// Distinct replacements suggested by a lint, up to a limit.
+#[derive(Default)]
+struct SuggestionSamples {
+    samples: Vec<String>,
+    /// Whether any distinct replacements were left out.
+    truncated: bool,
+}
+impl SuggestionSamples {
+    fn add(&mut self, replacement: String, max: usize) {
+        if !self.samples.contains(&replacement) {
+            if self.samples.len() < max {
+                self.samples.push(replacement);
+            } else {
+                self.truncated = true;
+            }
+        }
+    }
+}
+
+/// Collects the replacements of all suggestions in a diagnostic, including its children.
+fn suggested_replacements(d: &Diagnostic, replacements: &mut Vec<String>) {
+    replacements.extend(
+        d.spans
+            .iter()
+            .filter_map(|span| span.suggested_replacement.clone()),
+    );
+    for child in &d.children {
+        suggested_replacements(child, replacements);
+    }
+}
+
+/// The number of lines changed by suggestions.
+#[derive(Clone, Copy, Default)]
+struct Churn {
+    added: usize,
+    removed: usize,
+}
+impl Churn {
+    /// Counts the lines changed by all suggestions in a diagnostic, including its children.
+    fn of_diagnostic(d: &Diagnostic) -> Self {
+        let mut churn = d
+            .spans
+            .iter()
+            .filter_map(|span| {
+                let replacement = span.suggested_replacement.as_ref()?;
+                Some(Self {
+                    added: replacement.lines().count(),
+                    removed: span.line_end + 1 - span.line_start,
+                })
+            })
+            .fold(Self::default(), |x, y| x + y);
+        for child in &d.children {
+            churn += Self::of_diagnostic(child);
+        }
+        churn
+    }
+}
+impl ops::Add for Churn {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self {
+        Self {
+            added: self.added + rhs.added,
+            removed: self.removed + rhs.removed,
+        }
+    }
+}
+impl ops::AddAssign for Churn {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+impl fmt::Display for Churn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "+{} -{} lines", self.added, self.removed)
+    }
+}
+
+/// The reason a crate didn't produce any results.
+#[derive(Clone, Copy)]
+enum SkipReason {
+    /// An auto-published rustc crate.
+    RustcCrate,
+    ExtractionFailed,
+    /// The `.crate` file isn't in the crate cache.
+    MissingFromCache,
+    ManifestUnparseable,
+    /// Failed to build due to the current platform.
+    PlatformGated,
+    /// Uses an unstable feature which the current toolchain doesn't support.
+    NightlyFeatureMismatch,
+    /// A build script failed to find a system library.
+    MissingSystemLib,
+    /// A build script failed for any other reason.
+    BuildScriptFailed,
+    /// Failed to build after disabling the default features of dependencies.
+    MinimizedDepFeatures,
+    /// Has the same source as a crate which has already been checked.
+    Duplicate,
+    /// Failed to build in a previous run.
+    KnownBuildFailure,
+    /// The manifest has no library or binary targets.
+    NoTargets,
+    /// The only code is a build script.
+    NoSource,
+    /// Ran out of memory, usually due to `--max-memory`.
+    ResourceExceeded,
+    /// Any other error while checking the crate.
+    Error,
+}
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::RustcCrate => "rustc-ap",
+            Self::ExtractionFailed => "extraction-failed",
+            Self::MissingFromCache => "missing-from-cache",
+            Self::ManifestUnparseable => "manifest-unparseable",
+            Self::PlatformGated => "platform-gated",
+            Self::NightlyFeatureMismatch => "nightly-feature-mismatch",
+            Self::MissingSystemLib => "missing-system-lib",
+            Self::BuildScriptFailed => "build-script-failed",
+            Self::MinimizedDepFeatures => "minimized-dep-features",
+            Self::Duplicate => "duplicate-content",
+            Self::KnownBuildFailure => "known-build-failure",
+            Self::NoTargets => "no-targets",
+            Self::NoSource => "no-source",
+            Self::ResourceExceeded => "resource-exceeded",
+            Self::Error => "error",
+        })
+    }
+}
+
+/// Gets the names of the `count` most frequently built dependencies.
+fn most_used_deps(counts: &HashMap<String, usize>, count: usize) -> HashSet<String> {
+    let mut deps: Vec<_> = counts.iter().collect();
+    deps.sort_unstable_by(|x, y| x.1.cmp(y.1).reverse());
+    deps.into_iter()
+        .take(count)
+        .map(|(name, _)| name.clone())
+        .collect()
+}
+
+/// Removes everything from a profile's directory within the target directory except the build
+/// artifacts belonging to the given crates.
+fn clear_target_dir(profile_dir: &Path, keep: &HashSet<String>) {
+    if keep.is_empty() {
+        let _ = remove(profile_dir);
+        return;
+    }
+    let Ok(entries) = fs::read_dir(profile_dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if !matches!(
+            entry.file_name().to_str(),
+            Some("deps" | "build" | ".fingerprint")
+        ) {
+            // Includes `incremental`, which is only used by the checked crate.
+            let _ = remove(&path);
+            continue;
+        }
+        if let Ok(entries) = fs::read_dir(&path) {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if !path
+                    .file_name()
+                    .and_then(|name| artifact_crate_name(name.to_str()?))
+                    .is_some_and(|name| keep.contains(&name))
+                {
+                    let _ = remove(&path);
+                }
+            }
+        }
+    }
+}
+
+/// Checks that a profile name could be used with `cargo --profile`.
+fn check_profile_name(name: &str) -> Result<()> {
+    if name.is_empty()
+        || !name
+            .bytes()
+            .all(|c| c.is_ascii_alphanumeric() || c == b'-' || c == b'_')
+    {
+        bail!(
+            "invalid profile name `{}`: only letters, numbers, `-` and `_` are allowed",
+            name
+        );
+    }
+    if matches!(name, "debug" | "doc" | "build" | "check") {
+        bail!(
+            "invalid profile name `{}`: the name is reserved by cargo",
+            name
+        );
+    }
+    Ok(())
+}
+
+/// Gets the name of the directory a profile's artifacts are placed in.
+fn profile_dir_name(profile: &str) -> &str {
+    match profile {
+        "dev" | "test" => "debug",
+        "bench" => "release",
+        _ => profile,
+    }
+}
+
+/// Gets the normalized crate name from the name of an item in the target directory. e.g.
+/// `libserde-0123456789abcdef.rlib` or `proc-macro2-0123456789abcdef`.
+fn artifact_crate_name(file_name: &str) -> Option<String> {
+    let (name, hash) = file_name.rsplit_once('-')?;
+    let name = match hash.split_once('.') {
+        Some((_, "rlib" | "rmeta" | "so" | "dylib" | "a")) => name.strip_prefix("lib")?,
+        _ => name,
+    };
+    Some(name.replace('-', "_"))
+}
+
+/// Gets the directories containing `.crate` files. If `explicit` is set `root` is the only
+/// directory, otherwise each registry's cache directory within `root` is used, e.g. both the
+/// git and sparse crates.io caches. Directories which don't exist are ignored.
+fn find_cache_dirs(root: &Path, explicit: bool) -> Result<Vec<PathBuf>> {
+    if explicit {
+        return Ok(root.is_dir().then(|| root.to_owned()).into_iter().collect());
+    }
+    let entries = match fs::read_dir(root) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("error reading dir `{}`", root.display())),
+    };
+    let mut dirs = Vec::new();
+    for entry in entries {
+        let path = entry
+            .with_context(|| format!("error reading dir `{}`", root.display()))?
+            .path();
+        if path.is_dir() {
+            dirs.push(path);
+        }
+    }
+    // Keep the order stable so the same copy of a crate is always used.
+    dirs.sort();
+    Ok(dirs)
+}
+
+/// Gets the size of a crate's `.crate` file from the first directory containing it, or zero if
+/// it's not cached.
+fn crate_file_size(dirs: &[PathBuf], krate: &str) -> u64 {
+    let file_name = format!("{}.crate", krate);
+    dirs.iter()
+        .find_map(|dir| fs::metadata(dir.join(&file_name)).ok())
+        .map_or(0, |m| m.len())
+}
+
+/// Finds the latest versions of each crate in the given directories. Crates are deduplicated by
+/// their normalized name, but each version keeps the name from its file. A crate file present in
+/// multiple directories is only used once; `CrateSource::Cache` extracts the copy from the first
+/// directory containing it.
+fn find_crates(
+    dirs: &[PathBuf],
+    skipped: &mut Vec<(String, SkipReason)>,
+) -> Result<Vec<CachedCrate>> {
+    // Read each directory on its own thread.
+    let listings = thread::scope(|s| {
+        dirs.iter()
+            .map(|p| {
+                s.spawn(move || {
+                    fs::read_dir(p)
+                        .and_then(|entries| {
+                            entries
+                                .map(|entry| entry.map(|entry| entry.file_name()))
+                                .collect::<io::Result<Vec<_>>>()
+                        })
+                        .with_context(|| format!("error reading dir `{}`", p.display()))
+                })
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|t| t.join().expect("error reading crate cache dir"))
+            .collect::<Result<Vec<_>>>()
+    })?;
+    let mut seen = HashSet::new();
+    let files: Vec<_> = listings
+        .into_iter()
+        .flatten()
+        .filter(|name| seen.insert(name.clone()))
+        .collect();
+
+    // Parse the file names on each thread, then merge the results.
+    let thread_count = thread::available_parallelism().map_or(1, |x| x.get());
+    let chunk_size = files.len().div_ceil(thread_count).max(1);
+    let results = thread::scope(|s| {
+        files
+            .chunks(chunk_size)
+            .map(|chunk| s.spawn(|| parse_crate_files(chunk)))
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|t| t.join().expect("error parsing crate file names"))
+            .collect::<Vec<_>>()
+    });
+    Ok(merge_crate_versions(results, skipped)
+        .into_values()
+        .collect())
+}
+
+/// Merges the results of `parse_crate_files` on consecutive chunks of file names. The result is
+/// the same as parsing all the file names at once.
+fn merge_crate_versions(
+    results: Vec<(CrateVersions, Vec<(String, SkipReason)>)>,
+    skipped: &mut Vec<(String, SkipReason)>,
+) -> CrateVersions {
+    let mut crates = CrateVersions::new();
+    for (found, found_skipped) in results {
+        skipped.extend(found_skipped);
+        for (key, krate) in found {
+            match crates.entry(key) {
+                Entry::Vacant(entry) => {
+                    entry.insert(krate);
+                }
+                Entry::Occupied(mut entry) => entry.get_mut().merge(krate),
+            }
+        }
+    }
+    crates
+}
+
+/// Keeps at most `max` versions of a crate. The stable version is preferred, followed by the
+/// highest precedence pre-releases.
+fn limit_versions(ids: &mut Vec<CrateId>, max: usize) {
+    ids.sort_by(|x, y| match (x.version.pre(), y.version.pre()) {
+        (None, None) => cmp::Ordering::Equal,
+        (None, Some(_)) => cmp::Ordering::Less,
+        (Some(_), None) => cmp::Ordering::Greater,
+        (Some(x), Some(y)) => (y.stream, y.version).cmp(&(x.stream, x.version)),
+    });
+    ids.truncate(max);
+}
+
+/// Applies `--stable-only` and `--prerelease-only`.
+fn filter_release_channel(ids: &mut Vec<CrateId>, args: &Args) {
+    if args.stable_only {
+        ids.retain(|id| id.version.pre().is_none());
+    } else if args.prerelease_only {
+        ids.retain(|id| id.version.pre().is_some());
+    }
+}
+
+/// Prints the first step which would stop a crate from being checked when checking all cached
+/// crates.
+fn explain_skip(
+    args: &Args,
+    timestamp_format: &str,
+    crates_dirs: &[PathBuf],
+    krate: &str,
+) -> Result<()> {
+    let Some(id) = CrateId::parse(krate) else {
+        println!("`{}` isn't a crate id of the form `NAME-VERSION`", krate);
+        return Ok(());
+    };
+    let file_name = id.cache_file_name();
+    if !crates_dirs.iter().any(|dir| dir.join(&file_name).exists()) {
+        println!("`{}` isn't in the crate cache", file_name);
+        return Ok(());
+    }
+    if is_rustc_crate(id.name) {
+        println!(
+            "`{}` is an auto-published rustc crate (skipped as `{}`)",
+            krate,
+            SkipReason::RustcCrate
+        );
+        return Ok(());
+    }
+
+    let name = normalize_crate_name(id.name);
+    let crates = find_crates(crates_dirs, &mut Vec::new())?;
+    let Some(cached) = crates
+        .iter()
+        .find(|cached| normalize_crate_name(cached.name()) == name)
+    else {
+        println!("`{}` wasn't found when searching the crate cache", krate);
+        return Ok(());
+    };
+    let crate_name = cached.name();
+    let mut ids: Vec<_> = cached.iter_ids().collect();
+    let Some(found) = ids
+        .iter()
+        .find(|x| x.version == id.version)
+        .map(|x| x.to_string())
+    else {
+        let latest: Vec<_> = ids.iter().map(|x| format!("`{}`", x)).collect();
+        println!(
+            "`{}` isn't one of the latest versions of `{}`, which are {}",
+            krate,
+            crate_name,
+            latest.join(", ")
+        );
+        return Ok(());
+    };
+    filter_release_channel(&mut ids, args);
+    if !ids.iter().any(|x| x.version == id.version) {
+        let flag = if args.stable_only {
+            "--stable-only"
+        } else {
+            "--prerelease-only"
+        };
+        println!("`{}` is excluded by `{}`", found, flag);
+        return Ok(());
+    }
+    if let Some(max) = args.max_versions_per_crate {
+        limit_versions(&mut ids, max);
+        if !ids.iter().any(|x| x.version == id.version) {
+            println!(
+                "`{}` is excluded by `--max-versions-per-crate {}`",
+                found, max
+            );
+            return Ok(());
+        }
+    }
+    if let Some(path) = &args.since_report {
+        if baseline::read_done_crates(path)?.contains(&found) {
+            println!("`{}` was already checked by `{}`", found, path.display());
+            return Ok(());
+        }
+    }
+    if !args.retest_failed {
+        let blocklist = Blocklist::read(blocklist_path(&report_path(args, timestamp_format)?))?;
+        if blocklist.contains(&found) {
+            println!(
+                "`{}` failed to build in a previous run (skipped as `{}`)",
+                found,
+                SkipReason::KnownBuildFailure
+            );
+            return Ok(());
+        }
+    }
+    println!("`{}` would be checked", found);
+    Ok(())
+}
+
+/// The latest versions of a crate found in the crate cache.
+#[derive(Default)]
+struct CachedCrate {
+    versions: LatestVersions,
+    /// The name used by the file of each version. Names considered equivalent by crates.io may be
+    /// spelled differently between versions.
+    names: HashMap<String, String>,
+}
+impl CachedCrate {
+    fn push(&mut self, id: &CrateId) {
+        self.versions.push(id.version.clone());
+        if self.versions.iter_ids("").any(|x| x.version == id.version) {
+            self.names
+                .entry(id.version.to_string())
+                .or_insert_with(|| id.name.into());
+        }
+    }
+
+    fn merge(&mut self, other: CachedCrate) {
+        self.versions.merge(&other.versions);
+        for (version, name) in other.names {
+            self.names.entry(version).or_insert(name);
+        }
+    }
+
+    /// Gets the name used by the file of the first version returned by `iter_ids`.
+    fn name(&self) -> &str {
+        self.iter_ids().next().map_or("", |id| id.name)
+    }
+
+    /// Gets an iterator over all stable and pre-release versions, named as their files are.
+    fn iter_ids(&self) -> impl Iterator<Item = CrateId<'_>> {
+        self.versions.iter_ids("").map(move |mut id| {
+            id.name = self.names.get(&id.version.to_string()).map_or("", |x| x);
+            id
+        })
+    }
+}
+
+/// The latest versions of each crate, keyed by the normalized crate name.
+type CrateVersions = HashMap<String, CachedCrate>;
+
+/// Parses the names of crate files into the latest versions of each crate. Also returns any
+/// skipped crates.
+fn parse_crate_files(files: &[OsString]) -> (CrateVersions, Vec<(String, SkipReason)>) {
+    let mut crates = CrateVersions::new();
+    let mut skipped = Vec::new();
+    for file in files {
+        let Some(id) = file.to_str().and_then(CrateId::parse_filename) else {
+            continue;
+        };
+        if is_rustc_crate(id.name) {
+            // Ignore rustc crates as they likely won't build.
+            skipped.push((id.to_string(), SkipReason::RustcCrate));
+            continue;
+        }
+        crates
+            .entry(normalize_crate_name(id.name))
+            .or_default()
+            .push(&id);
+    }
+    (crates, skipped)
+}
+
+/// Adds the `clippy::` prefix to a lint name unless it already has a tool prefix, or it names a
+/// rustc lint.
+fn qualify_lint_name(name: String, lint_info: &HashMap<String, LintInfo>) -> String {
+    if name.contains("::")
+        || (lint_info.contains_key(&name) && !lint_info.contains_key(&format!("clippy::{}", name)))
+    {
+        name
+    } else {
+        format!("clippy::{}", name)
+    }
+}
+
+/// Converts a glob pattern into a regex matching the whole string. `*` matches any number of
+/// characters and `?` matches a single character.
+fn glob_regex(pattern: &str) -> Regex {
+    let re = regex::escape(pattern)
+        .replace(r"\*", ".*")
+        .replace(r"\?", ".");
+    Regex::new(&format!("^{}$", re)).unwrap()
+}
+
+/// Reads a list of lint names from a file. Each line contains a single lint name. Anything after a
+/// `#` is a comment.
+fn read_lint_list(p: &Path) -> Result<Vec<String>> {
+    Ok(fs::read_to_string(p)
+        .with_context(|| format!("error reading `{}`", p.display()))?
+        .lines()
+        .map(|line| line.split_once('#').map_or(line, |(line, _)| line).trim())
+        .filter(|line| !line.is_empty())
+        .map(String::from)
+        .collect())
+}
+
+fn parse_toml(p: &Path) -> Result<toml::Value> {
+    fs::read_to_string(p)
+        .with_context(|| format!("error reading `{}`", p.display()))?
+        .parse()
+        .with_context(|| format!("error parsing file `{}`", p.display()))
+}
+
+/// The `[toolchain]` table of a `rust-toolchain` file.
+struct Toolchain {
+    channel: String,
+    components: Vec<String>,
+    profile: Option<String>,
+}
+impl Toolchain {
+    fn parse(contents: &toml::Value) -> Result<Self, &'static str> {
+        let toolchain = contents
+            .get("toolchain")
+            .and_then(|t| t.as_table())
+            .ok_or("missing table `toolchain`")?;
+        let channel = toolchain
+            .get("channel")
+            .and_then(|c| c.as_str())
+            .ok_or("missing field `channel`")?
+            .to_owned();
+        let components = match toolchain.get("components") {
+            Some(toml::Value::Array(components)) => components
+                .iter()
+                .map(|c| c.as_str().map(String::from))
+                .collect::<Option<_>>()
+                .ok_or("invalid field `components`")?,
+            Some(_) => return Err("invalid field `components`"),
+            None => Vec::new(),
+        };
+        let profile = match toolchain.get("profile") {
+            Some(toml::Value::String(profile)) => Some(profile.clone()),
+            Some(_) => return Err("invalid field `profile`"),
+            None => None,
+        };
+        Ok(Self {
+            channel,
+            components,
+            profile,
+        })
+    }
+
+    /// Checks that the channel and all components are installed using `rustup`.
+    fn check_installed(&self) -> Result<()> {
+        let output = Command::new("rustup")
+            .args(["component", "list", "--installed", "--toolchain"])
+            .arg(&self.channel)
+            .output()
+            .context("error running `rustup`")?;
+        if !output.status.success() {
+            bail!(
+                "toolchain `{}` is not installed\nrun `rustup toolchain install {}{}` to install it",
+                self.channel,
+                self.channel,
+                self.components
+                    .iter()
+                    .fold(String::new(), |s, c| s + " --component " + c),
+            );
+        }
+        let installed =
+            str::from_utf8(&output.stdout).context("error converting `rustup` output to `str`")?;
+        let missing: Vec<_> = self
+            .components
+            .iter()
+            .filter(|&c| !is_component_installed(installed, c))
+            .map(String::as_str)
+            .collect();
+        if !missing.is_empty() {
+            bail!(
+                "toolchain `{}` is missing components: {}\nrun `rustup component add --toolchain {} {}` \
+                to install them",
+                self.channel,
+                missing.join(", "),
+                self.channel,
+                missing.join(" "),
+            );
+        }
+        Ok(())
+    }
+}
+impl fmt::Display for Toolchain {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.channel)?;
+        if let Some(profile) = &self.profile {
+            write!(f, ", profile `{}`", profile)?;
+        }
+        if !self.components.is_empty() {
+            write!(f, ", components: {}", self.components.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks if a component is in the output of `rustup component list --installed`. Installed
+/// components are listed with their target and without any `-preview` suffix, e.g.
+/// `llvm-tools-x86_64-unknown-linux-gnu`.
+fn is_component_installed(installed: &str, component: &str) -> bool {
+    let names = [component, component.trim_end_matches("-preview")];
+    installed.lines().map(str::trim).any(|line| {
+        names.iter().any(|name| {
+            line.strip_prefix(name)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('-'))
+        })
+    })
+}
+
+enum ClippyArgs {
+    /// Run clippy from it's source directory.
+    Source {
+        manifest: OsString,
+        toolchain: Toolchain,
+    },
+    /// Run prebuilt `cargo-clippy` and `clippy-driver` binaries.
+    Binary {
+        cargo_clippy: PathBuf,
+        driver: PathBuf,
+    },
+    /// Run the clippy installed by rustup.
+    Rustup {
+        /// The output of `cargo-clippy --version`.
+        version: String,
+    },
+}
+impl ClippyArgs {
+    /// Gets the toolchain used to build clippy, if it was built from source.
+    fn toolchain(&self) -> Option<&Toolchain> {
+        match self {
+            Self::Source { toolchain, .. } => Some(toolchain),
+            Self::Binary { .. } | Self::Rustup { .. } => None,
+        }
+    }
+
+    /// Gets the version of clippy, if it's installed by rustup.
+    fn version(&self) -> Option<&str> {
+        match self {
+            Self::Rustup { version } => Some(version),
+            Self::Source { .. } | Self::Binary { .. } => None,
+        }
+    }
+
     fn run_command(&self) -> Command {
-        let args: [&OsStr; 8] = [
-            self.channel.as_ref(),
-            "--quiet".as_ref(),
-            "run".as_ref(),
-            &self.manifest,
-            "--release".as_ref(),
-            "--bin".as_ref(),
-            "cargo-clippy".as_ref(),
-            "--".as_ref(),
-        ];
-        let mut command = Command::new("cargo");
-        command.args(args);
-        command
+        self.bin_command("cargo-clippy")
+    }
+
+    /// Creates a command which runs `clippy-driver` directly.
+    fn driver_command(&self) -> Command {
+        self.bin_command("clippy-driver")
+    }
+
+    fn bin_command(&self, bin: &str) -> Command {
+        match self {
+            Self::Source {
+                manifest,
+                toolchain,
+            } => {
+                let args: [&OsStr; 7] = [
+                    "--quiet".as_ref(),
+                    "run".as_ref(),
+                    manifest,
+                    "--release".as_ref(),
+                    "--bin".as_ref(),
+                    bin.as_ref(),
+                    "--".as_ref(),
+                ];
+                let mut command = toolchain_cargo(&toolchain.channel);
+                command.args(args);
+                command
+            }
+            Self::Binary {
+                cargo_clippy,
+                driver,
+            } => Command::new(if bin == "clippy-driver" {
+                driver
+            } else {
+                cargo_clippy
+            }),
+            // Rustup's proxies select the toolchain.
+            Self::Rustup { .. } => Command::new(bin),
+        }
+    }
+}
+
+/// Gets the path to write the report to. If `--report-file` is a directory, the default name is
+/// used within it.
+fn report_path(args: &Args, timestamp_format: &str) -> Result<PathBuf> {
+    let default_name = || -> PathBuf {
+        let name = args.clippy_dir.as_ref().and_then(|dir| {
+            let res = Command::new("git")
+                .args(["branch", "--show-current"])
+                .current_dir(dir)
+                .output();
+            res.map_or(None, |res| {
+                res.status
+                    .success()
+                    .then_some(())
+                    .and_then(|()| String::from_utf8(res.stdout).ok())
+            })
+        });
+        let date = if args.utc {
+            chrono::Utc::now().format(timestamp_format).to_string()
+        } else {
+            chrono::Local::now().format(timestamp_format).to_string()
+        };
+        let extension = args.report_format.extension();
+        let gz = if args.compress { ".gz" } else { "" };
+        if let Some(name) = name {
+            format!("{}-{}.{}{}", name.trim(), date, extension, gz)
+        } else {
+            format!("{}.{}{}", date, extension, gz)
+        }
+        .into()
+    };
+    match &args.report_name {
+        Some(path) if path.is_dir() => Ok(path.join(default_name())),
+        Some(path) if path.to_string_lossy().ends_with(path::is_separator) => {
+            bail!("report directory `{}` does not exist", path.display())
+        }
+        Some(path) => Ok(path.clone()),
+        None => Ok(default_name()),
+    }
+}
+
+/// Gets the path of the blocklist used with the given report.
+fn blocklist_path(report_path: &Path) -> PathBuf {
+    report_path.with_file_name(blocklist::FILE_NAME)
+}
+
+/// Gets the clippy to test, compiling it if needed. Progress is written to stderr so it doesn't mix
+/// with `--list-lints` output.
+fn build_clippy(args: &Args) -> Result<ClippyArgs> {
+    let sibling =
+        |path: &Path, bin: &str| path.with_file_name(format!("{}{}", bin, env::consts::EXE_SUFFIX));
+    let prebuilt = match (&args.cargo_clippy, &args.clippy_driver) {
+        (Some(cargo_clippy), Some(driver)) => Some((cargo_clippy.clone(), driver.clone())),
+        (Some(cargo_clippy), None) => {
+            Some((cargo_clippy.clone(), sibling(cargo_clippy, "clippy-driver")))
+        }
+        (None, Some(driver)) => Some((sibling(driver, "cargo-clippy"), driver.clone())),
+        (None, None) => None,
+    };
+    match (&args.clippy_dir, prebuilt) {
+        (None, Some((cargo_clippy, driver))) => Ok(ClippyArgs::Binary {
+            cargo_clippy,
+            driver,
+        }),
+        (Some(dir), None) => {
+            eprintln!("Compiling clippy...");
+            compile_clippy(dir, args.show_full_build_log)
+        }
+        (None, None) => {
+            let output = Command::new("cargo-clippy")
+                .arg("--version")
+                .output()
+                .ok()
+                .filter(|output| output.status.success())
+                .context(
+                    "either a clippy directory, `--cargo-clippy` or `--clippy-driver` must be \
+                    given, or clippy must be installed with `rustup component add clippy`",
+                )?;
+            let version = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+            eprintln!("Using {}", version);
+            Ok(ClippyArgs::Rustup { version })
+        }
+        (Some(_), Some(_)) => {
+            bail!("`--cargo-clippy` and `--clippy-driver` can't be used with a clippy directory")
+        }
     }
 }
 
-fn compile_clippy(p: &Path) -> Result<ClippyArgs> {
-    let toolchain = p.join("rust-toolchain");
-    let contents = parse_toml(&toolchain)?;
-    let mut channel_arg = String::from("+");
-    if let toml::Value::Table(contents) = contents {
-        if let Some(toml::Value::Table(contents)) = contents.get("toolchain") {
-            if let Some(toml::Value::String(channel)) = contents.get("channel") {
-                channel_arg.push_str(channel);
-            } else {
-                bail!(
-                    "error parsing `{}`: missing field `channel`",
-                    toolchain.display()
-                );
-            }
-        } else {
-            bail!(
-                "error parsing `{}`: missing table `toolchain`",
-                toolchain.display()
-            );
-        }
-    } else {
-        bail!(
-            "error parsing `{}`: missing root table",
-            toolchain.display()
-        );
+/// Prints a tab separated table of clippy's lints sorted by name.
+fn list_lints(clippy_args: &ClippyArgs) -> Result<()> {
+    let lint_info = lint_info::query(clippy_args.driver_command())?;
+    let mut lints: Vec<_> = lint_info
+        .iter()
+        .filter(|(name, _)| name.starts_with("clippy::"))
+        .collect();
+    lints.sort_unstable_by(|x, y| x.0.cmp(y.0));
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    for (name, info) in lints {
+        writeln!(
+            stdout,
+            "{}\t{}\t{}\t{}",
+            name,
+            info.group.as_deref().unwrap_or("-"),
+            info.default_level,
+            info.description
+        )?;
     }
+    Ok(())
+}
+
+fn compile_clippy(p: &Path, show_full_log: bool) -> Result<ClippyArgs> {
+    let toolchain_path = p.join("rust-toolchain");
+    let toolchain = match Toolchain::parse(&parse_toml(&toolchain_path)?) {
+        Ok(toolchain) => toolchain,
+        Err(e) => bail!("error parsing `{}`: {}", toolchain_path.display(), e),
+    };
+    toolchain.check_installed()?;
     let mut manifest_arg: OsString = "--manifest-path=".into();
     manifest_arg.push(p.join("Cargo.toml"));
 
-    let args: [&OsStr; 4] = [
-        channel_arg.as_ref(),
-        "build".as_ref(),
-        &manifest_arg,
-        "--release".as_ref(),
-    ];
-    let output = Command::new("cargo")
+    let args: [&OsStr; 3] = ["build".as_ref(), &manifest_arg, "--release".as_ref()];
+    let output = toolchain_cargo(&toolchain.channel)
         .args(args)
         .output()
         .context("error running `cargo`")?;
     if !output.status.success() {
+        let stderr =
+            str::from_utf8(&output.stderr).context("error converting `cargo` output to `str`")?;
+        let summary = if show_full_log {
+            None
+        } else {
+            summarize_build_errors(stderr)
+        };
         bail!(
             "Failed to build clippy ({}):\n{}",
             output.status,
-            str::from_utf8(&output.stderr).context("error converting `cargo` output to `str`")?
+            summary.as_deref().unwrap_or(stderr)
         );
     }
 
-    Ok(ClippyArgs {
+    Ok(ClippyArgs::Source {
         manifest: manifest_arg,
-        channel: channel_arg,
+        toolchain,
+    })
+}
+
+/// Creates a `cargo` command which uses the given toolchain. Rustup's proxy takes the toolchain as
+/// an argument, any other binary gets it through `RUSTUP_TOOLCHAIN`.
+fn toolchain_cargo(channel: &str) -> Command {
+    if env::var_os("CARGO").is_some() {
+        let mut command = cargo_command();
+        command.env("RUSTUP_TOOLCHAIN", channel);
+        command
+    } else {
+        let mut command = Command::new("cargo");
+        command.arg(format!("+{}", channel));
+        command
+    }
+}
+
+/// Extracts the error headers and their locations from rustc's output. Returns `None` if no errors
+/// were found.
+fn summarize_build_errors(stderr: &str) -> Option<String> {
+    let mut summary = String::new();
+    let mut lines = stderr.lines().peekable();
+    while let Some(line) = lines.next() {
+        if line.starts_with("error") && !line.starts_with("error: could not compile") {
+            summary.push_str(line);
+            summary.push('\n');
+            if let Some(loc) = lines.next_if(|l| l.trim_start().starts_with("-->")) {
+                summary.push_str(loc);
+                summary.push('\n');
+            }
+        }
+    }
+    (!summary.is_empty()).then(|| {
+        summary.push_str("\nrerun with `--show-full-build-log` to see the full output\n");
+        summary
     })
 }
 
+/// Creates a uniquely named directory inside `base` to use in place of the system temp dir.
+fn create_work_dir(base: &Path) -> Result<PathBuf> {
+    if !base.is_dir() {
+        bail!("work dir `{}` does not exist", base.display());
+    }
+    let dir = base.join(format!("clippy_lint_test-{}", std::process::id()));
+    fs::create_dir(&dir)
+        .with_context(|| format!("error creating dir in work dir `{}`", base.display()))?;
+    Ok(dir)
+}
+
 struct RemoveOnDrop<'a>(&'a Path);
 impl Drop for RemoveOnDrop<'_> {
     fn drop(&mut self) {
@@ -328,44 +2605,226 @@ impl Drop for RemoveOnDrop<'_> {
 
 #[derive(Default)]
 struct RunOutput {
-    pub lint_msgs: Vec<String>,
+    /// The lint name and rendered message of each lint emitted.
+    pub lint_msgs: Vec<(String, String)>,
+    /// The location of each lint emitted which has a primary span.
+    pub lint_spans: Vec<LintSpan>,
+    /// The lint name and replacement of each suggestion made by an emitted lint.
+    pub suggestions: Vec<(String, String)>,
     pub err_msg: String,
     pub ice_msg: String,
     pub fix_msg: String,
+    /// The names of all dependencies built or reused.
+    pub artifacts: Vec<String>,
+    /// The dependency with multiple candidates if E0464 persists after clearing the target
+    /// directory.
+    pub ambiguous_dep: Option<String>,
+    /// Why the crate couldn't be checked, if it couldn't.
+    pub skipped: Option<SkipReason>,
+    /// The number of lines each lint's suggestions would change.
+    pub churn: HashMap<String, Churn>,
+    /// Whether a denied lint stopped the build.
+    pub denied: bool,
+    /// Whether the build stopped before finishing.
+    pub incomplete: bool,
+    /// Whether `rlib` was added to the library's crate types.
+    pub added_rlib: bool,
+    /// The hash of the crate's source, excluding the files generated when packaging it.
+    pub content_hash: Option<u64>,
+    /// The previously checked crate with the same source.
+    pub duplicate_of: Option<String>,
+    /// The name and version of each dependency in `Cargo.lock` after checking, if requested.
+    pub resolved_deps: Option<Vec<(String, String)>>,
+    /// The categories and keywords from the crate's manifest.
+    pub categories: Vec<String>,
+    pub keywords: Vec<String>,
+    /// A shell script which reproduces the ICE, if there was one.
+    pub ice_repro: Option<String>,
+    /// The output of the compared clippy.
+    pub compared: Option<Box<RunOutput>>,
+}
+impl RunOutput {
+    fn skipped(reason: SkipReason, err_msg: String) -> Self {
+        Self {
+            err_msg,
+            skipped: Some(reason),
+            ..Self::default()
+        }
+    }
 }
 
 enum RunResult {
-    Complete(RunOutput),
+    Complete(Box<RunOutput>),
     MultipleCrates(String),
     FailedParseManifest(String),
 }
 impl From<RunOutput> for RunResult {
     fn from(f: RunOutput) -> Self {
-        Self::Complete(f)
+        Self::Complete(Box::new(f))
+    }
+}
+
+/// Options controlling how each crate is checked.
+struct CheckOptions<'a> {
+    /// Which lint messages to accept.
+    filter: MessageFilter<'a>,
+    /// Whether to collect the replacements suggested by each lint.
+    collect_suggestions: bool,
+    /// Whether to check that `clippy --fix` succeeds.
+    fix: bool,
+    /// Additional lints to allow.
+    allow: &'a [String],
+    /// Whether to disable the default features of dependencies.
+    minimize_dep_features: bool,
+    /// Additional environment variables for the build.
+    env: &'a [EnvVar],
+    /// The level to test each lint at.
+    levels: &'a HashMap<String, LintLevel>,
+    /// Which targets to check.
+    targets: Targets,
+    /// Whether to use the crate's `Cargo.lock`.
+    keep_lockfile: bool,
+    /// Whether to read the resolved dependencies from `Cargo.lock` after checking.
+    record_deps: bool,
+    /// Paths which shouldn't be removed from the crate.
+    keep: &'a [PathBuf],
+    /// Whether to skip crates whose only code is a build script.
+    skip_build_script_only: bool,
+    /// The number of jobs cargo can run in parallel.
+    cargo_jobs: Option<usize>,
+    /// The cargo profile to build with.
+    profile: Option<&'a str>,
+    /// The directory to store each crate's incremental data in. Incremental compilation is
+    /// disabled if this isn't set.
+    incremental_dir: Option<&'a Path>,
+    /// The address space limit of the build in megabytes.
+    max_memory: Option<u64>,
+    /// Where to copy the source of crates which cause an ICE.
+    keep_sources: Option<&'a Path>,
+    /// Whether to hash each crate's source for the report.
+    hash_content: bool,
+    /// The crate each source hash was first seen in, if crates with the same source are skipped.
+    content_hashes: Option<&'a RefCell<HashMap<u64, String>>>,
+    /// A second clippy to check each crate with, and the target directory it uses.
+    compare: Option<(&'a ClippyArgs, &'a Path)>,
+    /// The directory the second clippy stores each crate's incremental data in.
+    compare_incremental_dir: Option<&'a Path>,
+}
+
+/// Which lint messages to accept.
+#[derive(Clone, Copy, Default)]
+struct MessageFilter<'a> {
+    regex: Option<&'a Regex>,
+    /// A shell command which is passed each message on stdin.
+    command: Option<&'a str>,
+}
+impl MessageFilter<'_> {
+    fn accepts(&self, msg: &str) -> Result<bool> {
+        if self.regex.is_some_and(|re| !re.is_match(msg)) {
+            return Ok(false);
+        }
+        let Some(command) = self.command else {
+            return Ok(true);
+        };
+        let mut command = if cfg!(target_os = "windows") {
+            let mut c = Command::new("cmd");
+            c.args(["/C", command]);
+            c
+        } else {
+            let mut c = Command::new("sh");
+            c.args(["-c", command]);
+            c
+        };
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()
+            .context("error running filter command")?;
+        // The command may exit without reading its input.
+        let _ = child.stdin.take().unwrap().write_all(msg.as_bytes());
+        Ok(child
+            .wait()
+            .context("error running filter command")?
+            .success())
     }
 }
 
-#[allow(clippy::too_many_arguments)]
 fn check_crate(
     clippy_args: &ClippyArgs,
+    options: &CheckOptions,
     target_dir: &Path,
     lints: &mut HashMap<String, usize>,
-    crates_dir: &Path,
+    source: CrateSource,
     krate: &str,
-    filter: Option<&Regex>,
-    fix: bool,
     temp_dir: &Path,
 ) -> Result<RunOutput> {
-    extract_crate(&crates_dir.join(format!("{}.crate", krate)), temp_dir)?;
-
-    let path = temp_dir.join(krate);
+    if let CrateSource::Cache(crates_dirs) = source {
+        if find_crate_file(crates_dirs, krate).is_none() {
+            return Ok(RunOutput::skipped(
+                SkipReason::MissingFromCache,
+                format!("`{}.crate` not found in the crate cache\n", krate),
+            ));
+        }
+    }
+    let hash_content = options.hash_content || options.content_hashes.is_some();
+    let (path, hash) = match source.unpack(krate, temp_dir, hash_content) {
+        Ok(x) => x,
+        Err(e) => {
+            return Ok(RunOutput::skipped(
+                SkipReason::ExtractionFailed,
+                format!("{:#}\n", e),
+            ))
+        }
+    };
     let _delayed = RemoveOnDrop(&path);
-    remove_file(&path.join(".cargo").join("config"))?;
-    remove_file(&path.join("Cargo.lock"))?;
+    if let (Some(content_hashes), Some(hash)) = (options.content_hashes, hash) {
+        let mut content_hashes = content_hashes.borrow_mut();
+        if let Some(original) = content_hashes.get(&hash) {
+            return Ok(RunOutput {
+                duplicate_of: Some(original.clone()),
+                skipped: Some(SkipReason::Duplicate),
+                content_hash: Some(hash),
+                ..RunOutput::default()
+            });
+        }
+        content_hashes.insert(hash, krate.into());
+    }
+    let is_kept = |item: &str| {
+        options
+            .keep
+            .iter()
+            .any(|kept| kept.strip_prefix(".").unwrap_or(kept) == Path::new(item))
+    };
+    if !is_kept(".cargo/config") {
+        remove_file(&path.join(".cargo").join("config"))?;
+    }
+    if !options.keep_lockfile && !is_kept("Cargo.lock") {
+        remove_file(&path.join("Cargo.lock"))?;
+    }
     let manifest_path = path.join("Cargo.toml");
-    let mut manifest = prepare_manifest(&manifest_path, &path.join("Cargo.toml.orig"))?;
-    let _ = remove(&path.join("tests"));
-    let _ = remove(&path.join("benches"));
+    let (mut manifest, changes) = match prepare_manifest(
+        &manifest_path,
+        &path.join("Cargo.toml.orig"),
+        options.minimize_dep_features,
+    ) {
+        Ok(x) => x,
+        Err(e) => {
+            return Ok(RunOutput::skipped(
+                SkipReason::ManifestUnparseable,
+                format!("{:#}\n", e),
+            ))
+        }
+    };
+    if options.skip_build_script_only && is_build_script_only(&path, &manifest) {
+        return Ok(RunOutput::skipped(SkipReason::NoSource, String::new()));
+    }
+    if !options.targets.tests && !is_kept("tests") && !has_main_target_in(&manifest, "tests") {
+        let _ = remove(path.join("tests"));
+    }
+    if !options.targets.benches && !is_kept("benches") && !has_main_target_in(&manifest, "benches")
+    {
+        let _ = remove(path.join("benches"));
+    }
 
     let args: [&OsStr; 7] = [
         "--".as_ref(), // command name
@@ -377,61 +2836,290 @@ fn check_crate(
         target_dir.as_ref(),
     ];
     let mut command = clippy_args.run_command();
+    let clippy_arg_count = command.get_args().len();
     command.args(args);
-    if fix {
+    command.envs(options.env.iter().map(|var| (&var.key, &var.value)));
+    if let Some(jobs) = options.cargo_jobs {
+        command.arg(format!("--jobs={}", jobs));
+    }
+    if let Some(profile) = options.profile {
+        command.args(["--profile", profile]);
+    }
+    if options.targets != Targets::default() {
+        // `--lib` is an error if the crate doesn't have a library.
+        let has_lib = path.join("src").join("lib.rs").exists() || manifest.get("lib").is_some();
+        let targets = &options.targets;
+        for (flag, selected) in [
+            ("--lib", targets.lib && has_lib),
+            ("--bins", targets.bins),
+            ("--tests", targets.tests),
+            ("--benches", targets.benches),
+            ("--examples", targets.examples),
+        ] {
+            if selected {
+                command.arg(flag);
+            }
+        }
+    }
+    if options.fix {
         command.args(["--fix", "--allow-no-vcs"]);
     }
-    let args: [&OsStr; 7] = [
-        "--".as_ref(),
-        "--cap-lints".as_ref(),
-        "warn".as_ref(),
-        "--allow".as_ref(),
-        "clippy::all".as_ref(),
-        "-C".as_ref(),
-        "incremental=false".as_ref(),
-    ];
-    command.args(args);
+    command.arg("--");
+    // Capping lints would stop denied lints from failing the build. Lints can't be capped while
+    // raising only the tested ones, so any of the crate's own denied lints stop the build as well.
+    if options
+        .levels
+        .values()
+        .all(|&level| level == LintLevel::Warn)
+    {
+        command.args(["--cap-lints", "warn"]);
+    }
+    command.args(["--allow", "clippy::all", "-C"]);
+    command.arg(incremental_arg(options.incremental_dir, krate));
+    for lint in options.allow {
+        command.args(["--allow", lint]);
+    }
     for lint in lints.keys() {
-        let args: [&OsStr; 2] = ["--warn".as_ref(), lint.as_ref()];
+        let level = options.levels.get(lint).copied().unwrap_or(LintLevel::Warn);
+        let args: [&OsStr; 2] = [level.flag().as_ref(), lint.as_ref()];
         command.args(args);
     }
 
+    #[cfg(unix)]
+    if let Some(max_memory) = options.max_memory {
+        limit_memory(&mut command, max_memory);
+    }
+
     let mut failed_parse_manifest = false;
     let mut multiple_crates = false;
-    loop {
-        match compile_crate(&mut command, lints, filter)? {
-            RunResult::Complete(x) => break Ok(x),
+    let mut output = loop {
+        match compile_crate(&mut command, lints, options)? {
+            RunResult::Complete(x) => break *x,
             RunResult::FailedParseManifest(_) if !failed_parse_manifest => {
                 failed_parse_manifest = true;
                 let removed = write_without_extras(&mut manifest, &manifest_path)?;
-                if removed.benches {
-                    let _ = remove(&path.join("benches"));
-                }
-                if removed.examples {
-                    let _ = remove(&path.join("examples"));
-                }
-                if removed.tests {
-                    let _ = remove(&path.join("tests"));
+                for (dir, removed) in [
+                    ("benches", removed.benches),
+                    ("examples", removed.examples),
+                    ("tests", removed.tests),
+                ] {
+                    if removed && !has_main_target_in(&manifest, dir) {
+                        let _ = remove(path.join(dir));
+                    }
                 }
             }
             RunResult::MultipleCrates(_) if !multiple_crates => {
                 multiple_crates = true;
                 let _ = remove(target_dir);
             }
-            RunResult::FailedParseManifest(m) | RunResult::MultipleCrates(m) => {
-                break Ok(RunOutput {
+            RunResult::MultipleCrates(m) => {
+                break match parse_ambiguous_dep(&m) {
+                    Some(dep) => RunOutput {
+                        ambiguous_dep: Some(dep.into()),
+                        ..RunOutput::default()
+                    },
+                    None => RunOutput {
+                        err_msg: m,
+                        ..RunOutput::default()
+                    },
+                };
+            }
+            RunResult::FailedParseManifest(m) => {
+                break RunOutput::skipped(SkipReason::ManifestUnparseable, m);
+            }
+        }
+    };
+    if let (Some((compare_clippy, compare_target_dir)), None) = (options.compare, output.skipped) {
+        let mut compare_command = with_other_clippy(
+            &command,
+            clippy_arg_count,
+            compare_clippy,
+            compare_target_dir,
+            &incremental_arg(options.compare_incremental_dir, krate),
+        );
+        #[cfg(unix)]
+        if let Some(max_memory) = options.max_memory {
+            limit_memory(&mut compare_command, max_memory);
+        }
+        // Only the tested clippy's results are counted.
+        let mut counts = lints.keys().map(|lint| (lint.clone(), 0)).collect();
+        let compared = match compile_crate(&mut compare_command, &mut counts, options)? {
+            RunResult::Complete(x) => x,
+            RunResult::MultipleCrates(m) | RunResult::FailedParseManifest(m) => {
+                Box::new(RunOutput {
                     err_msg: m,
                     ..RunOutput::default()
-                });
+                })
+            }
+        };
+        output.compared = Some(compared);
+    }
+    output.added_rlib = changes.added_rlib;
+    if options.record_deps {
+        output.resolved_deps = fs::read_to_string(path.join("Cargo.lock"))
+            .ok()
+            .and_then(|contents| parse_lockfile_deps(&contents));
+    }
+    output.categories = package_strings(&manifest, "categories");
+    output.keywords = package_strings(&manifest, "keywords");
+    output.content_hash = hash;
+    if changes.minimized && output.skipped.is_none() && !output.err_msg.is_empty() {
+        // The failure is likely caused by a missing feature rather than by the crate itself.
+        output.skipped = Some(SkipReason::MinimizedDepFeatures);
+    }
+    if !output.ice_msg.is_empty() {
+        let kept = options.keep_sources.and_then(|keep_dir| {
+            let kept = keep_dir.join(path.file_name().unwrap_or(krate.as_ref()));
+            match copy_dir(&path, &kept) {
+                Ok(()) => Some(kept),
+                Err(e) => {
+                    eprintln!("warning: error copying dir `{}`: {}", path.display(), e);
+                    None
+                }
+            }
+        });
+        let setup = if let Some(kept) = kept {
+            format!("cd {}", shell_quote(&kept.to_string_lossy()))
+        } else {
+            let removed_lockfile = !options.keep_lockfile && !is_kept("Cargo.lock");
+            format!(
+                "{}\n{}",
+                source.setup_script(krate, &path),
+                prepared_source_script(&path, removed_lockfile)
+            )
+        };
+        output.ice_repro = Some(repro_script(&command, &setup, &manifest_path));
+    }
+    Ok(output)
+}
+
+/// Creates shell commands which make the same changes to a freshly unpacked crate as were made
+/// before checking it: the rewritten manifest is restored and removed files are removed again.
+fn prepared_source_script(path: &Path, removed_lockfile: bool) -> String {
+    let mut script = String::new();
+    let removed = [".cargo/config", "benches", "examples", "tests"]
+        .into_iter()
+        .filter(|item| !path.join(item).exists())
+        .chain(removed_lockfile.then_some("Cargo.lock"));
+    for item in removed {
+        script.push_str("rm -rf ");
+        script.push_str(item);
+        script.push('\n');
+    }
+    match fs::read_to_string(path.join("Cargo.toml")) {
+        Ok(manifest) => {
+            script.push_str("cat > Cargo.toml <<'CLIPPY_LINT_TEST_EOF'\n");
+            script.push_str(&manifest);
+            if !manifest.ends_with('\n') {
+                script.push('\n');
             }
+            script.push_str("CLIPPY_LINT_TEST_EOF");
+        }
+        Err(_) => script.push_str(
+            "# The rewritten `Cargo.toml` couldn't be read, so this may not reproduce the same build.",
+        ),
+    }
+    script
+}
+
+/// Creates a shell script which reruns the command used to check a crate. `setup` must change to the
+/// crate's directory.
+fn repro_script(command: &Command, setup: &str, manifest_path: &Path) -> String {
+    let mut script = String::new();
+    for (key, value) in command.get_envs() {
+        if let Some(value) = value {
+            script.push_str(&shell_quote(&format!(
+                "{}={}",
+                key.to_string_lossy(),
+                value.to_string_lossy()
+            )));
+            script.push(' ');
+        }
+    }
+    script.push_str(&shell_quote(&command.get_program().to_string_lossy()));
+    let mut args = command.get_args();
+    while let Some(arg) = args.next() {
+        if arg == "--target-dir" {
+            args.next();
+        } else if arg != "--message-format=json" {
+            let arg = if arg == manifest_path.as_os_str() {
+                "Cargo.toml".into()
+            } else {
+                arg.to_string_lossy()
+            };
+            script.push(' ');
+            script.push_str(&shell_quote(&arg));
+        }
+    }
+    format!("{}\n{}\n", setup, script)
+}
+
+/// Quotes a string for use as a single shell argument.
+fn shell_quote(s: &str) -> String {
+    if !s.is_empty()
+        && s.bytes()
+            .all(|c| c.is_ascii_alphanumeric() || b"-_./=:+,@%".contains(&c))
+    {
+        s.into()
+    } else {
+        format!("'{}'", s.replace('\'', "'\\''"))
+    }
+}
+
+/// Gets the `-C incremental` value storing a crate's incremental data within `dir`, or disabling
+/// incremental compilation if there's no directory.
+fn incremental_arg(dir: Option<&Path>, krate: &str) -> OsString {
+    if let Some(dir) = dir {
+        let mut arg = OsString::from("incremental=");
+        arg.push(dir.join(krate));
+        arg
+    } else {
+        "incremental=false".into()
+    }
+}
+
+/// Creates a command which runs the same check as `command` using a different clippy, target
+/// directory and `-C incremental` value. The first `clippy_arg_count` arguments are the ones used
+/// to run the original clippy.
+fn with_other_clippy(
+    command: &Command,
+    clippy_arg_count: usize,
+    clippy_args: &ClippyArgs,
+    target_dir: &Path,
+    incremental: &OsStr,
+) -> Command {
+    let mut res = clippy_args.run_command();
+    let mut args = command.get_args().skip(clippy_arg_count).peekable();
+    while let Some(arg) = args.next() {
+        res.arg(arg);
+        if arg == "--target-dir" {
+            args.next();
+            res.arg(target_dir);
+        } else if arg == "-C"
+            && args
+                .peek()
+                .is_some_and(|arg| arg.as_encoded_bytes().starts_with(b"incremental="))
+        {
+            args.next();
+            res.arg(incremental);
+        }
+    }
+    // The toolchain belongs to the tested clippy.
+    for (key, value) in command
+        .get_envs()
+        .filter(|&(key, _)| key != "RUSTUP_TOOLCHAIN")
+    {
+        if let Some(value) = value {
+            res.env(key, value);
         }
     }
+    res
 }
 
 fn compile_crate(
     c: &mut Command,
     lints: &mut HashMap<String, usize>,
-    filter: Option<&Regex>,
+    options: &CheckOptions,
 ) -> Result<RunResult> {
     let output = c.output().context("error running `cargo`")?;
 
@@ -440,6 +3128,12 @@ fn compile_crate(
         str::from_utf8(&output.stderr).context("error converting `cargo` stderr to `str`")?;
 
     if !output.status.success() {
+        if is_out_of_memory(stderr) {
+            result.err_msg = format!("ran out of memory ({}):\n", output.status);
+            result.err_msg.push_str(stderr);
+            result.skipped = Some(SkipReason::ResourceExceeded);
+            return Ok(result.into());
+        }
         if stderr.contains("failed to run custom build command") {
             if let Some((pkg, _)) = stderr
                 .split("\n--- stderr\n")
@@ -452,115 +3146,599 @@ fn compile_crate(
                 .find(|(_, msg)| msg.contains("was not found in the pkg-config search path"))
             {
                 result.err_msg = format!("pkg-config error: missing package `{}`\n", pkg);
+                result.skipped = Some(SkipReason::MissingSystemLib);
+                return Ok(result.into());
+            }
+            if let Some((pkg, msg)) = parse_build_script_error(stderr) {
+                result.err_msg = format!("build script failed for `{}`: {}\n", pkg, msg);
+                result.skipped = Some(SkipReason::BuildScriptFailed);
                 return Ok(result.into());
             }
         } else if stderr.contains("failed to parse manifest at") {
             result.err_msg = format!("error running clippy ({}):\n", output.status);
             result.err_msg.push_str(stderr);
+            if stderr.contains("no targets specified in the manifest") {
+                // Removing extra targets won't help.
+                result.skipped = Some(SkipReason::NoTargets);
+                return Ok(result.into());
+            }
             return Ok(RunResult::FailedParseManifest(result.err_msg));
         }
         result.err_msg = format!("error running clippy ({}):\n", output.status);
     }
 
-    for m in Message::parse_stream(output.stdout.as_slice()) {
-        let m = m.context("error parsing `cargo` output")?;
-        if let Message::CompilerMessage(CompilerMessage { message: m, .. }) = m {
-            match (m.level, m.code, m.rendered) {
-                (DiagnosticLevel::Warning, Some(c), Some(m)) => {
-                    if let Some(count) = lints.get_mut(&c.code) {
-                        if filter.map_or(true, |f| f.is_match(&m)) {
-                            *count += 1;
-                            result.lint_msgs.push(m);
-                        }
+    let mut other_errors = false;
+    let mut dep_errors = false;
+    let mut finished = false;
+    for m in Message::parse_stream(output.stdout.as_slice()) {
+        let m = m.context("error parsing `cargo` output")?;
+        if let Message::CompilerMessage(CompilerMessage {
+            message: m,
+            package_id,
+            ..
+        }) = m
+        {
+            dep_errors |= m.level == DiagnosticLevel::Error && !is_local_package(&package_id);
+            let churn = Churn::of_diagnostic(&m);
+            let mut replacements = Vec::new();
+            if options.collect_suggestions {
+                suggested_replacements(&m, &mut replacements);
+            }
+            let Diagnostic {
+                message,
+                level,
+                code,
+                spans,
+                rendered,
+                ..
+            } = m;
+            match (level, code, rendered) {
+                (level @ (DiagnosticLevel::Warning | DiagnosticLevel::Error), Some(c), Some(m))
+                    if lints.contains_key(&c.code) =>
+                {
+                    // Any tested lint emitted as an error will stop the build.
+                    result.denied |= level == DiagnosticLevel::Error;
+                    if options.filter.accepts(&m)? {
+                        *lints.get_mut(&c.code).unwrap() += 1;
+                        *result.churn.entry(c.code.clone()).or_default() += churn;
+                        result.suggestions.extend(
+                            replacements
+                                .into_iter()
+                                .map(|replacement| (c.code.clone(), replacement)),
+                        );
+                        if let Some(s) = spans.into_iter().find(|s| s.is_primary) {
+                            result.lint_spans.push(LintSpan {
+                                lint: c.code.clone(),
+                                message,
+                                denied: level == DiagnosticLevel::Error,
+                                file: s.file_name,
+                                line_start: s.line_start,
+                                column_start: s.column_start,
+                                line_end: s.line_end,
+                                column_end: s.column_end,
+                            });
+                        }
+                        result.lint_msgs.push((c.code, m));
+                    }
+                }
+                (DiagnosticLevel::Error, Some(c), Some(m)) if c.code == "E0464" => {
+                    result.err_msg.push_str(&m);
+                    return Ok(RunResult::MultipleCrates(result.err_msg));
+                }
+                (DiagnosticLevel::Error, ref c, Some(ref m))
+                    if is_platform_error(c.as_ref().map(|c| c.code.as_str()), m) =>
+                {
+                    // Platform dependent errors - don't report
+                    result.err_msg = String::new();
+                    result.skipped = Some(SkipReason::PlatformGated);
+                    break;
+                }
+                (DiagnosticLevel::Error, Some(c), Some(m)) if is_feature_error(&c.code, &m) => {
+                    // Written for a different nightly - don't report
+                    result.err_msg = String::new();
+                    result.skipped = Some(SkipReason::NightlyFeatureMismatch);
+                    break;
+                }
+                (DiagnosticLevel::Error, Some(c), Some(_)) if is_lint_code(&c.code) => {
+                    // Lints are only uncapped when a tested lint is denied. The crate's own
+                    // denied lints then stop the build the same way.
+                    result.denied = true;
+                }
+                (DiagnosticLevel::Error, _, Some(m)) => {
+                    other_errors = true;
+                    result.err_msg.push_str(&m);
+                }
+                _ => (),
+            }
+        } else if let Message::CompilerArtifact(a) = m {
+            if !a.target.kind.iter().any(|k| k == "custom-build") {
+                result.artifacts.push(a.target.name.replace('-', "_"));
+            }
+        } else if let Message::BuildFinished(_) = m {
+            finished = true;
+        }
+    }
+    // Cargo reports when a failed build finishes as well. Diagnostics are only missing if cargo
+    // was stopped, or a dependency failed before the crate was checked.
+    result.incomplete = !output.status.success() && (!finished || dep_errors);
+
+    if !output.status.success() {
+        if is_ice(stderr) {
+            result.ice_msg = stderr.to_owned();
+        } else if stderr.contains("failed to automatically apply fixes") {
+            result.fix_msg = stderr.to_owned();
+        } else if result.denied && !other_errors {
+            // The build was only stopped by the tested lints.
+            result.err_msg = String::new();
+        } else {
+            result.err_msg.push_str(stderr);
+        }
+    }
+
+    Ok(result.into())
+}
+
+/// Checks if a package is built from a local path, rather than being a dependency from a registry
+/// or a git repository. Handles both the old and new package id formats.
+fn is_local_package(id: &PackageId) -> bool {
+    id.repr.starts_with("path+") || id.repr.contains(" (path+")
+}
+
+/// Checks if a diagnostic code names a lint rather than being an error code such as `E0308`.
+fn is_lint_code(code: &str) -> bool {
+    !code
+        .strip_prefix('E')
+        .is_some_and(|num| !num.is_empty() && num.bytes().all(|c| c.is_ascii_digit()))
+}
+
+/// Checks if an error is caused by building on an unsupported platform. Messages are matched
+/// case-insensitively.
+fn is_platform_error(code: Option<&str>, msg: &str) -> bool {
+    let msg = msg.to_lowercase();
+    match code {
+        Some("E0432" | "E0433") if msg.contains("use winapi") => true,
+        Some("E0455") if msg.contains("link kind `framework`") => true,
+        Some(_) => msg.contains("use std::os::windows"),
+        None => {
+            msg.contains("use std::os::windows")
+                || msg.contains("macosx or ios")
+                || msg.contains("macos or ios")
+                || msg.contains("is deprecated")
+                || msg.contains("renamed to")
+        }
+    }
+}
+
+/// Checks if an error is caused by the crate's use of unstable features not matching those supported
+/// by the current toolchain.
+fn is_feature_error(code: &str, msg: &str) -> bool {
+    match code {
+        // `#![feature]` used on a stable toolchain, or a feature not in `-Z allow-features`
+        "E0554" | "E0725" => true,
+        // use of an unstable feature which isn't enabled
+        "E0658" => true,
+        "E0557" => msg.contains("feature has been removed"),
+        "E0635" => msg.contains("unknown feature"),
+        _ => false,
+    }
+}
+
+/// Extracts the package and the first line of the build script's stderr from a failed build script
+/// error. A panic's message is included with its location.
+fn parse_build_script_error(stderr: &str) -> Option<(&str, String)> {
+    let (_, rest) = stderr.split_once("failed to run custom build command for `")?;
+    let (pkg, rest) = rest.split_once('`')?;
+    let mut lines = rest
+        .lines()
+        .map(str::trim)
+        .skip_while(|&l| l != "--- stderr")
+        .skip(1)
+        .take_while(|l| !l.starts_with("--- "))
+        .filter(|l| !l.is_empty());
+    let line = lines.next()?;
+    let msg = match lines.next() {
+        Some(next) if line.contains("panicked at") && line.ends_with(':') => {
+            format!("{} {}", line, next)
+        }
+        _ => line.to_owned(),
+    };
+    Some((pkg, msg))
+}
+
+/// The starts of lines which indicate the compiler crashed.
+const ICE_MARKERS: [&str; 4] = [
+    "error: internal compiler error",
+    "thread 'rustc' panicked",
+    "error: the compiler unexpectedly panicked",
+    "error: delayed span bug",
+];
+
+/// Checks if the compiler output contains an internal compiler error.
+fn is_ice(stderr: &str) -> bool {
+    stderr
+        .lines()
+        .any(|line| ICE_MARKERS.iter().any(|m| line.starts_with(m)))
+}
+
+/// Checks if a process ran out of memory. Allocation failures abort the process rather than
+/// causing an ICE.
+fn is_out_of_memory(stderr: &str) -> bool {
+    stderr.lines().any(|line| {
+        (line.starts_with("memory allocation of ") && line.ends_with(" failed"))
+            || line.starts_with("LLVM ERROR: out of memory")
+    })
+}
+
+/// Limits the address space of the command's process, which is inherited by all its children.
+#[cfg(unix)]
+fn limit_memory(command: &mut Command, megabytes: u64) {
+    use std::os::unix::process::CommandExt;
+
+    let bytes = megabytes.saturating_mul(1024 * 1024) as libc::rlim_t;
+    let limit = libc::rlimit {
+        rlim_cur: bytes,
+        rlim_max: bytes,
+    };
+    // SAFETY: `setrlimit` is async-signal-safe.
+    unsafe {
+        command.pre_exec(move || {
+            if libc::setrlimit(libc::RLIMIT_AS, &limit) == 0 {
+                Ok(())
+            } else {
+                Err(io::Error::last_os_error())
+            }
+        });
+    }
+}
+
+/// Extracts the name of the conflicting dependency from an E0464 error message. e.g.
+/// ``error[E0464]: multiple candidates for `rlib` dependency `foo` found``
+fn parse_ambiguous_dep(msg: &str) -> Option<&str> {
+    let line = msg.lines().find(|l| l.contains("[E0464]"))?;
+    let (rest, _) = line.rsplit_once('`')?;
+    let (_, name) = rest.rsplit_once('`')?;
+    Some(name)
+}
+
+/// Where the crates to check come from.
+#[derive(Clone, Copy)]
+enum CrateSource<'a> {
+    /// Cargo's crate cache directories, in order of preference.
+    Cache(&'a [PathBuf]),
+    /// A crate in a local directory.
+    Path(&'a Path),
+    /// A crate in a git repository.
+    Git(&'a str),
+}
+impl CrateSource<'_> {
+    /// Creates a shell command which places a copy of the crate in the current directory and changes
+    /// to its directory. `path` is where the crate was unpacked.
+    fn setup_script(self, krate: &str, path: &Path) -> String {
+        let dir_name = path
+            .file_name()
+            .map_or(krate.into(), |x| x.to_string_lossy());
+        match self {
+            Self::Cache(crates_dirs) => {
+                let file = find_crate_file(crates_dirs, krate)
+                    .map_or_else(|| format!("{}.crate", krate), |x| x.display().to_string());
+                format!(
+                    "tar -xzf {} && cd {}",
+                    shell_quote(&file),
+                    shell_quote(&dir_name)
+                )
+            }
+            Self::Path(path) => format!(
+                "cp -R {} {} && cd {}",
+                shell_quote(&path.to_string_lossy()),
+                shell_quote(&dir_name),
+                shell_quote(&dir_name)
+            ),
+            Self::Git(url) => format!(
+                "git clone --depth 1 {} {} && cd {}",
+                shell_quote(url),
+                shell_quote(&dir_name),
+                shell_quote(&dir_name)
+            ),
+        }
+    }
+
+    /// Places a copy of the crate in `temp_dir`. Returns the directory containing the crate, which
+    /// is normally `temp_dir/krate`, and the hash of its source if `hash` is set.
+    fn unpack(self, krate: &str, temp_dir: &Path, hash: bool) -> Result<(PathBuf, Option<u64>)> {
+        let target = match self {
+            Self::Cache(crates_dirs) => {
+                let file = find_crate_file(crates_dirs, krate)
+                    .with_context(|| format!("`{}.crate` not found in the crate cache", krate))?;
+                // Crate files are hashed while they're extracted.
+                return extract_crate(&file, temp_dir, hash);
+            }
+            Self::Path(path) => {
+                let target = temp_dir.join(krate);
+                copy_dir(path, &target)
+                    .with_context(|| format!("error copying dir `{}`", path.display()))?;
+                target
+            }
+            Self::Git(url) => {
+                let target = temp_dir.join(krate);
+                let output = Command::new("git")
+                    .args(["clone", "--quiet", "--depth", "1", url])
+                    .arg(&target)
+                    .output()
+                    .context("error running `git`")?;
+                if !output.status.success() {
+                    bail!(
+                        "error cloning `{}` ({}):\n{}",
+                        url,
+                        output.status,
+                        String::from_utf8_lossy(&output.stderr)
+                    );
+                }
+                target
+            }
+        };
+        let hash = hash
+            .then(|| hash_source(&target))
+            .transpose()
+            .with_context(|| format!("error reading dir `{}`", target.display()))?;
+        Ok((target, hash))
+    }
+}
+
+/// Finds the first cache directory containing the crate's file.
+fn find_crate_file(crates_dirs: &[PathBuf], krate: &str) -> Option<PathBuf> {
+    let file_name = format!("{}.crate", krate);
+    crates_dirs
+        .iter()
+        .map(|dir| dir.join(&file_name))
+        .find(|file| file.exists())
+}
+
+/// Files which differ between otherwise identical releases of a crate.
+const PACKAGE_METADATA_FILES: [&str; 4] = [
+    "Cargo.toml",
+    "Cargo.toml.orig",
+    "Cargo.lock",
+    ".cargo_vcs_info.json",
+];
+
+/// Hashes the paths and contents of all files in a crate directory. See `SourceHasher`.
+fn hash_source(root: &Path) -> io::Result<u64> {
+    fn hash_dir(root: &Path, dir: &Path, hasher: &mut SourceHasher) -> io::Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                hash_dir(root, &path, hasher)?;
+            } else {
+                hasher.add(path.strip_prefix(root).unwrap_or(&path), &fs::read(&path)?);
+            }
+        }
+        Ok(())
+    }
+
+    let mut hasher = SourceHasher::default();
+    hash_dir(root, root, &mut hasher)?;
+    Ok(hasher.finish())
+}
+
+/// Hashes the paths and contents of all files in a crate, excluding the package metadata files.
+/// Each file is hashed separately and the results are combined in path order, so files can be added
+/// in any order.
+#[derive(Default)]
+struct SourceHasher(Vec<(PathBuf, u64)>);
+impl SourceHasher {
+    /// Adds a file. `relative` is the file's path from the crate's root.
+    fn add(&mut self, relative: &Path, contents: &[u8]) {
+        if PACKAGE_METADATA_FILES
+            .iter()
+            .any(|&name| relative == Path::new(name))
+        {
+            return;
+        }
+        let mut hasher = Fnv1a::new();
+        for c in relative.components() {
+            hasher.write(c.as_os_str().as_encoded_bytes());
+            hasher.write(b"/");
+        }
+        hasher.write(&(contents.len() as u64).to_le_bytes());
+        hasher.write(contents);
+        self.0.push((relative.to_owned(), hasher.0));
+    }
+
+    fn finish(mut self) -> u64 {
+        self.0.sort_unstable();
+        let mut hasher = Fnv1a::new();
+        for (_, hash) in self.0 {
+            hasher.write(&hash.to_le_bytes());
+        }
+        hasher.0
+    }
+}
+
+/// 64-bit FNV-1a. Unlike `DefaultHasher` its output is the same on every toolchain, so hashes can
+/// be compared across runs.
+struct Fnv1a(u64);
+impl Fnv1a {
+    fn new() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 = (self.0 ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+/// Recursively copies a directory, skipping symlinks and any `target` and `.git` directories.
+fn copy_dir(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let path = entry.path();
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            if name != "target" && name != ".git" {
+                copy_dir(&path, &to.join(&name))?;
+            }
+        } else if file_type.is_file() {
+            fs::copy(&path, to.join(&name))?;
+        }
+    }
+    Ok(())
+}
+
+/// Extracts a `.crate` file into the target directory. Returns the crate's root directory as named in
+/// the archive, which may differ from the file name, and the hash of its source if `hash` is set.
+fn extract_crate(file: &Path, target: &Path, hash: bool) -> Result<(PathBuf, Option<u64>)> {
+    let mut archive =
+        Archive::new(GzDecoder::new(fs::File::open(file).with_context(|| {
+            format!("error opening file `{}`", file.display())
+        })?));
+    let err_ctx = || format!("error unpacking file `{}`", file.display());
+    fs::create_dir_all(target).with_context(err_ctx)?;
+    let canonical_target = fs::canonicalize(target).with_context(err_ctx)?;
+    let mut root = None;
+    let mut hasher = hash.then(SourceHasher::default);
+    for entry in archive.entries().with_context(err_ctx)? {
+        let mut entry = entry.with_context(err_ctx)?;
+        let path = entry.path().with_context(err_ctx)?.into_owned();
+        let Some(path) = normalize_relative(&path) else {
+            bail!(
+                "error unpacking file `{}`: entry `{}` escapes the extraction directory",
+                file.display(),
+                path.display(),
+            );
+        };
+        if root.is_none() {
+            root = path.components().next().map(|c| target.join(c));
+        }
+        if matches!(
+            entry.header().entry_type(),
+            EntryType::Symlink | EntryType::Link
+        ) {
+            let Some(link) = entry.link_name().with_context(err_ctx)? else {
+                continue;
+            };
+            // Symlinks are relative to the containing directory, hard links to the archive root.
+            // Both are resolved through any symlinks already unpacked.
+            let dir = if entry.header().entry_type() == EntryType::Symlink {
+                let parent = path.parent().unwrap_or(Path::new(""));
+                let Some(dir) = resolve_in(&canonical_target, canonical_target.clone(), parent)
+                else {
+                    continue;
+                };
+                fs::create_dir_all(&dir).with_context(err_ctx)?;
+                dir
+            } else {
+                canonical_target.clone()
+            };
+            if resolve_in(&canonical_target, dir, &link).is_none() {
+                continue;
+            }
+        }
+        // `unpack_in` refuses to write through symlinks leading outside of `target`.
+        let unpacked = entry.unpack_in(target).with_context(err_ctx)?;
+        if let Some(hasher) = &mut hasher {
+            if unpacked && entry.header().entry_type().is_file() {
+                let contents = fs::read(target.join(&path)).with_context(err_ctx)?;
+                let mut components = path.components();
+                components.next();
+                hasher.add(components.as_path(), &contents);
+            }
+        }
+    }
+    let root =
+        root.with_context(|| format!("error unpacking file `{}`: empty archive", file.display()))?;
+    Ok((root, hasher.map(SourceHasher::finish)))
+}
+
+/// Resolves a relative path starting from `dir`, an existing directory inside `root`, following
+/// any symlinks already on disk. Returns `None` if the path leaves `root` or goes up from a
+/// directory which doesn't exist yet, since a symlink created there later could point anywhere.
+fn resolve_in(root: &Path, mut dir: PathBuf, path: &Path) -> Option<PathBuf> {
+    let mut exists = true;
+    for c in path.components() {
+        match c {
+            Component::Normal(c) => {
+                dir.push(c);
+                if exists {
+                    match fs::canonicalize(&dir) {
+                        Ok(canonical) => dir = canonical,
+                        Err(_) => exists = false,
                     }
                 }
-                (DiagnosticLevel::Error, Some(c), Some(m)) if c.code == "E0464" => {
-                    result.err_msg.push_str(&m);
-                    return Ok(RunResult::MultipleCrates(result.err_msg));
-                }
-                (DiagnosticLevel::Error, Some(c), Some(m))
-                    if ((c.code == "E0432" || c.code == "E0433") && m.contains("use winapi")
-                        || m.contains("use std::os::windows"))
-                        || (c.code == "E0455" && m.contains("link kind `framework`"))
-                        || (c.code == "E0557" && m.contains("feature has been removed"))
-                        || (c.code == "E0635" && m.contains("unknown feature")) =>
-                {
-                    // Platform dependent errors - don't report
-                    result.err_msg = String::new();
-                    break;
-                }
-                (DiagnosticLevel::Error, None, Some(m))
-                    if m.contains("MacOSX or iOS")
-                        || m.contains("macos or ios")
-                        || m.contains("is deprecated")
-                        || m.contains("Renamed to") =>
-                {
-                    // Platform dependent errors - don't report
-                    result.err_msg = String::new();
-                    break;
-                }
-                (DiagnosticLevel::Error, _, Some(m)) => {
-                    result.err_msg.push_str(&m);
+            }
+            Component::CurDir => (),
+            Component::ParentDir => {
+                if !exists || !dir.pop() {
+                    return None;
                 }
-                _ => (),
             }
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+        if !dir.starts_with(root) {
+            return None;
         }
     }
+    Some(dir)
+}
 
-    if !output.status.success() {
-        if stderr.contains("internal compiler error:") {
-            result.ice_msg = stderr.to_owned();
-        } else if stderr.contains("failed to automatically apply fixes") {
-            result.fix_msg = stderr.to_owned();
-        } else {
-            result.err_msg.push_str(stderr);
+/// Lexically normalizes a relative path. Returns `None` if the path is absolute or would
+/// leave the directory it's relative to.
+fn normalize_relative(path: &Path) -> Option<PathBuf> {
+    let mut res = PathBuf::new();
+    for c in path.components() {
+        match c {
+            Component::Normal(c) => res.push(c),
+            Component::CurDir => (),
+            Component::ParentDir => {
+                if !res.pop() {
+                    return None;
+                }
+            }
+            Component::RootDir | Component::Prefix(_) => return None,
         }
     }
-
-    Ok(result.into())
+    Some(res)
 }
 
-fn extract_crate(file: &Path, target: &Path) -> Result<()> {
-    let mut archive =
-        Archive::new(GzDecoder::new(fs::File::open(file).with_context(|| {
-            format!("error opening file `{}`", file.display())
-        })?));
-    archive
-        .unpack(target)
-        .with_context(|| format!("error unpacking file `{}`", file.display()))
+/// Changes made to a crate's manifest which can affect the results.
+#[derive(Default)]
+struct ManifestChanges {
+    /// The default features of dependencies were disabled.
+    minimized: bool,
+    /// `rlib` was added to the library's crate types.
+    added_rlib: bool,
 }
 
-fn prepare_manifest(path: &Path, orig_path: &Path) -> Result<toml::Value> {
+/// Reads the crate's manifest and rewrites it so the crate can be built outside of its workspace.
+fn prepare_manifest(
+    path: &Path,
+    orig_path: &Path,
+    minimize_dep_features: bool,
+) -> Result<(toml::Value, ManifestChanges)> {
     let mut contents: toml::Value = fs::read_to_string(orig_path)
         .or_else(|_| fs::read_to_string(path))
         .with_context(|| format!("error reading file `{}`", path.display()))?
         .parse()
         .with_context(|| format!("error parsing file `{}`", path.display()))?;
 
+    let mut changes = ManifestChanges::default();
     if let toml::Value::Table(table) = &mut contents {
+        changes.minimized = minimize_dep_features && disable_dep_default_features(table);
+        changes.added_rlib = add_rlib_crate_type(table);
         if table.remove("workspace").is_some()
-            | table
-                .get_mut("dependencies")
-                .map_or(false, remove_toml_path_deps)
-            | table
-                .get_mut("build-dependencies")
-                .map_or(false, remove_toml_path_deps)
-            | table
-                .get_mut("dev-dependencies")
-                .map_or(false, remove_toml_path_deps)
-            | table
-                .iter_mut()
-                .filter(|&(name, _)| name.starts_with("target") && name.ends_with("dependencies"))
-                .fold(false, |update, (_, value)| {
-                    update | remove_toml_path_deps(value)
-                })
+            | remove_manifest_path_deps(table)
+            | changes.minimized
+            | changes.added_rlib
         {
             fs::write(path, contents.to_string())
                 .with_context(|| format!("error writing file `{}`", path.display()))?;
         }
     }
 
-    Ok(contents)
+    Ok((contents, changes))
 }
 
 #[derive(Default)]
@@ -584,6 +3762,116 @@ fn write_without_extras(value: &mut toml::Value, path: &Path) -> Result<ExtrasRe
     Ok(res)
 }
 
+/// Checks if the source of the library or any binary is inside the given directory. Such
+/// directories can't be removed without leaving the crate with nothing to build.
+fn has_main_target_in(manifest: &toml::Value, dir: &str) -> bool {
+    main_target_paths(manifest).any(|target_path| Path::new(target_path).starts_with(dir))
+}
+
+/// Gets the name and version of every package from a registry or git repository in a lockfile. The
+/// crate itself and other path dependencies are excluded.
+fn parse_lockfile_deps(contents: &str) -> Option<Vec<(String, String)>> {
+    let lockfile: toml::Value = toml::from_str(contents).ok()?;
+    let mut deps: Vec<_> = lockfile
+        .get("package")?
+        .as_array()?
+        .iter()
+        .filter(|package| package.get("source").is_some())
+        .filter_map(|package| {
+            Some((
+                package.get("name")?.as_str()?.to_owned(),
+                package.get("version")?.as_str()?.to_owned(),
+            ))
+        })
+        .collect();
+    deps.sort_unstable();
+    Some(deps)
+}
+
+/// Gets the strings in an array field of the manifest's package table, e.g. `keywords`. Any other
+/// values are ignored.
+fn package_strings(manifest: &toml::Value, field: &str) -> Vec<String> {
+    manifest
+        .get("package")
+        .and_then(|package| package.get(field)?.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|value| Some(value.as_str()?.to_owned()))
+        .collect()
+}
+
+/// Gets the paths set for the library and binaries in the manifest.
+fn main_target_paths(manifest: &toml::Value) -> impl Iterator<Item = &str> {
+    let lib = manifest.get("lib").into_iter();
+    let bins = manifest
+        .get("bin")
+        .and_then(|bins| bins.as_array())
+        .into_iter()
+        .flatten();
+    lib.chain(bins)
+        .filter_map(|target| target.get("path")?.as_str())
+}
+
+/// Checks if a crate's only code is its build script. i.e. it has a build script, all the library
+/// and binary targets are under `src` and every file there is empty apart from comments and inner
+/// attributes.
+fn is_build_script_only(path: &Path, manifest: &toml::Value) -> bool {
+    let has_build_script = path.join("build.rs").exists()
+        || manifest
+            .get("package")
+            .and_then(|package| package.get("build"))
+            .is_some_and(|build| build.is_str());
+    if !has_build_script || !main_target_paths(manifest).all(|p| Path::new(p).starts_with("src")) {
+        return false;
+    }
+    let mut found_source = false;
+    let mut dirs = vec![path.join("src")];
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            return false;
+        };
+        for entry in entries {
+            let Ok(entry) = entry else {
+                return false;
+            };
+            let path = entry.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path.extension().is_some_and(|ext| ext == "rs") {
+                match fs::read_to_string(&path) {
+                    Ok(contents) if is_empty_source(&contents) => found_source = true,
+                    _ => return false,
+                }
+            }
+        }
+    }
+    found_source
+}
+
+/// Checks if a source file contains nothing but comments and inner attributes.
+fn is_empty_source(contents: &str) -> bool {
+    let mut rest = contents.trim_start();
+    while !rest.is_empty() {
+        rest = if let Some(comment) = rest.strip_prefix("//") {
+            comment.split_once('\n').map_or("", |(_, rest)| rest)
+        } else if let Some(comment) = rest.strip_prefix("/*") {
+            match comment.split_once("*/") {
+                Some((_, rest)) => rest,
+                None => return false,
+            }
+        } else if let Some(attr) = rest.strip_prefix("#![") {
+            match attr.split_once(']') {
+                Some((_, rest)) => rest,
+                None => return false,
+            }
+        } else {
+            return false;
+        }
+        .trim_start();
+    }
+    true
+}
+
 fn remove_file(p: &Path) -> Result<()> {
     match fs::remove_file(p) {
         Ok(()) => Ok(()),
@@ -592,11 +3880,108 @@ fn remove_file(p: &Path) -> Result<()> {
     }
 }
 
+/// The names of all dependency tables, including the deprecated snake case forms.
+const DEPENDENCY_TABLES: [&str; 5] = [
+    "dependencies",
+    "dev-dependencies",
+    "dev_dependencies",
+    "build-dependencies",
+    "build_dependencies",
+];
+
+/// Removes path dependencies from all dependency tables in a manifest, including target specific
+/// dependencies.
+fn remove_manifest_path_deps(manifest: &mut toml::value::Table) -> bool {
+    fn remove_from_dep_tables(table: &mut toml::value::Table) -> bool {
+        table
+            .iter_mut()
+            .filter(|(name, _)| DEPENDENCY_TABLES.contains(&name.as_str()))
+            .fold(false, |removed, (_, deps)| {
+                removed | remove_toml_path_deps(deps)
+            })
+    }
+
+    let mut removed = remove_from_dep_tables(manifest);
+    if let Some(toml::Value::Table(targets)) = manifest.get_mut("target") {
+        for (_, target) in targets.iter_mut() {
+            if let toml::Value::Table(target) = target {
+                removed |= remove_from_dep_tables(target);
+            }
+        }
+    }
+    removed
+}
+
+/// Adds `rlib` to the library's crate types if it only builds a `cdylib`, `staticlib` or other
+/// non-rust library. This avoids needing a linker for the crate.
+fn add_rlib_crate_type(manifest: &mut toml::value::Table) -> bool {
+    let Some(toml::Value::Table(lib)) = manifest.get_mut("lib") else {
+        return false;
+    };
+    let key = if lib.contains_key("crate-type") {
+        "crate-type"
+    } else {
+        "crate_type"
+    };
+    let Some(toml::Value::Array(types)) = lib.get_mut(key) else {
+        return false;
+    };
+    if types.is_empty()
+        || types
+            .iter()
+            .any(|ty| matches!(ty.as_str(), Some("lib" | "rlib" | "dylib" | "proc-macro")))
+    {
+        return false;
+    }
+    types.push(toml::Value::String("rlib".into()));
+    true
+}
+
+/// Sets `default-features = false` on all normal dependencies, including target specific
+/// dependencies. Dependencies which already specify `default-features` are left alone.
+fn disable_dep_default_features(manifest: &mut toml::value::Table) -> bool {
+    fn disable_in_deps(table: &mut toml::value::Table) -> bool {
+        let Some(toml::Value::Table(deps)) = table.get_mut("dependencies") else {
+            return false;
+        };
+        deps.iter_mut().fold(false, |changed, (_, dep)| {
+            if let toml::Value::String(version) = dep {
+                let mut table = toml::value::Table::new();
+                table.insert("version".into(), toml::Value::String(version.clone()));
+                *dep = toml::Value::Table(table);
+            }
+            match dep {
+                toml::Value::Table(dep)
+                    if !dep.contains_key("default-features")
+                        && !dep.contains_key("default_features") =>
+                {
+                    dep.insert("default-features".into(), toml::Value::Boolean(false));
+                    true
+                }
+                _ => changed,
+            }
+        })
+    }
+
+    let mut changed = disable_in_deps(manifest);
+    if let Some(toml::Value::Table(targets)) = manifest.get_mut("target") {
+        for (_, target) in targets.iter_mut() {
+            if let toml::Value::Table(target) = target {
+                changed |= disable_in_deps(target);
+            }
+        }
+    }
+    changed
+}
+
+/// Replaces path dependencies and dependencies inherited from the workspace with registry
+/// dependencies.
 fn remove_toml_path_deps(deps: &mut toml::Value) -> bool {
     if let toml::Value::Table(deps) = deps {
         deps.iter_mut().fold(false, |removed, (_, dep)| {
             if let toml::Value::Table(dep) = dep {
-                if dep.remove("path").is_some() {
+                // The workspace table has already been removed.
+                if dep.remove("path").is_some() | dep.remove("workspace").is_some() {
                     dep.entry("version")
                         .or_insert_with(|| toml::Value::String("*".into()));
                     return true;
@@ -608,3 +3993,1027 @@ fn remove_toml_path_deps(deps: &mut toml::Value) -> bool {
         false
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{
+        add_rlib_crate_type, check_profile_name, clear_target_dir, copy_dir,
+        disable_dep_default_features, extract_crate, find_crates, glob_regex, has_main_target_in,
+        hash_source, is_component_installed, is_empty_source, is_feature_error, is_ice,
+        is_lint_code, is_local_package, is_out_of_memory, is_platform_error, merge_crate_versions,
+        normalize_relative, package_strings, parse_build_script_error, parse_crate_files,
+        parse_lockfile_deps, prepared_source_script, profile_dir_name, qualify_lint_name,
+        remove_manifest_path_deps, repro_script, suggested_replacements, with_other_clippy,
+        CacheSize, CachedCrate, Churn, ClippyArgs, Diagnostic, ErrorSignatures, Histogram,
+        LintInfo, MessageFilter, SuggestionSamples, Toolchain,
+    };
+    use flate2::{write::GzEncoder, Compression};
+    use std::{
+        collections::{HashMap, HashSet},
+        fs,
+        path::{Path, PathBuf},
+    };
+
+    fn remove_path_deps(manifest: &str) -> toml::value::Table {
+        let mut manifest = toml::from_str(manifest).unwrap();
+        assert!(remove_manifest_path_deps(&mut manifest));
+        manifest
+    }
+
+    fn dep<'a>(table: &'a toml::value::Table, path: &[&str]) -> &'a toml::value::Table {
+        path.iter()
+            .fold(table, |table, name| table[*name].as_table().unwrap())
+    }
+
+    #[test]
+    fn remove_path_deps_inline_table() {
+        let manifest = remove_path_deps(
+            r#"
+            [dependencies]
+            foo = { path = "../foo", features = ["bar"] }
+            baz = { path = "../baz", version = "1.0" }
+            "#,
+        );
+        let foo = dep(&manifest, &["dependencies", "foo"]);
+        assert!(!foo.contains_key("path"));
+        assert_eq!(foo["version"].as_str(), Some("*"));
+        assert!(foo.contains_key("features"));
+        let baz = dep(&manifest, &["dependencies", "baz"]);
+        assert!(!baz.contains_key("path"));
+        assert_eq!(baz["version"].as_str(), Some("1.0"));
+    }
+
+    #[test]
+    fn remove_path_deps_table_header() {
+        let manifest = remove_path_deps(
+            r#"
+            [dev-dependencies.foo]
+            path = "../foo"
+            "#,
+        );
+        let foo = dep(&manifest, &["dev-dependencies", "foo"]);
+        assert!(!foo.contains_key("path"));
+        assert_eq!(foo["version"].as_str(), Some("*"));
+    }
+
+    #[test]
+    fn remove_path_deps_dotted_keys() {
+        let manifest = remove_path_deps(
+            r#"
+            dependencies.foo.path = "../foo"
+
+            [build-dependencies]
+            bar.path = "../bar"
+            bar.version = "0.1"
+            "#,
+        );
+        let foo = dep(&manifest, &["dependencies", "foo"]);
+        assert!(!foo.contains_key("path"));
+        assert_eq!(foo["version"].as_str(), Some("*"));
+        let bar = dep(&manifest, &["build-dependencies", "bar"]);
+        assert!(!bar.contains_key("path"));
+        assert_eq!(bar["version"].as_str(), Some("0.1"));
+    }
+
+    #[test]
+    fn remove_path_deps_target() {
+        let manifest = remove_path_deps(
+            r#"
+            [target.'cfg(unix)'.dependencies]
+            foo = { path = "../foo" }
+
+            [target.x86_64-pc-windows-msvc.build-dependencies.bar]
+            path = "../bar"
+            "#,
+        );
+        let foo = dep(&manifest, &["target", "cfg(unix)", "dependencies", "foo"]);
+        assert!(!foo.contains_key("path"));
+        assert_eq!(foo["version"].as_str(), Some("*"));
+        let bar = dep(
+            &manifest,
+            &[
+                "target",
+                "x86_64-pc-windows-msvc",
+                "build-dependencies",
+                "bar",
+            ],
+        );
+        assert!(!bar.contains_key("path"));
+        assert_eq!(bar["version"].as_str(), Some("*"));
+    }
+
+    #[test]
+    fn remove_path_deps_workspace() {
+        let manifest = remove_path_deps(
+            r#"
+            [dependencies]
+            foo.workspace = true
+            bar = { workspace = true, optional = true }
+            "#,
+        );
+        for name in ["foo", "bar"] {
+            let dep = dep(&manifest, &["dependencies", name]);
+            assert!(!dep.contains_key("workspace"));
+            assert_eq!(dep["version"].as_str(), Some("*"));
+        }
+    }
+
+    #[test]
+    fn minimize_dep_features() {
+        let mut manifest = toml::from_str(
+            r#"
+            [dependencies]
+            foo = "1.0"
+            bar = { version = "0.2", default-features = true }
+            baz = { version = "0.3", features = ["std"] }
+
+            [dev-dependencies]
+            qux = "1.0"
+
+            [target.'cfg(unix)'.dependencies]
+            libc = "0.2"
+            "#,
+        )
+        .unwrap();
+        assert!(disable_dep_default_features(&mut manifest));
+        let foo = dep(&manifest, &["dependencies", "foo"]);
+        assert_eq!(foo["version"].as_str(), Some("1.0"));
+        assert_eq!(foo["default-features"].as_bool(), Some(false));
+        let bar = dep(&manifest, &["dependencies", "bar"]);
+        assert_eq!(bar["default-features"].as_bool(), Some(true));
+        let baz = dep(&manifest, &["dependencies", "baz"]);
+        assert_eq!(baz["default-features"].as_bool(), Some(false));
+        assert!(manifest["dev-dependencies"]["qux"].is_str());
+        let libc = dep(&manifest, &["target", "cfg(unix)", "dependencies", "libc"]);
+        assert_eq!(libc["default-features"].as_bool(), Some(false));
+    }
+
+    #[test]
+    fn rlib_crate_type() {
+        let mut manifest = toml::from_str(
+            r#"
+            [lib]
+            crate-type = ["cdylib", "staticlib"]
+            "#,
+        )
+        .unwrap();
+        assert!(add_rlib_crate_type(&mut manifest));
+        assert_eq!(
+            manifest["lib"]["crate-type"].as_array().unwrap(),
+            &["cdylib", "staticlib", "rlib"].map(|x| toml::Value::String(x.into()))
+        );
+
+        let mut manifest = toml::from_str(
+            r#"
+            [lib]
+            crate-type = ["cdylib", "rlib"]
+            "#,
+        )
+        .unwrap();
+        assert!(!add_rlib_crate_type(&mut manifest));
+        let mut manifest = toml::from_str("[lib]\nname = \"foo\"\n").unwrap();
+        assert!(!add_rlib_crate_type(&mut manifest));
+    }
+
+    #[test]
+    fn cache_size() {
+        let parse = |s: &str| s.parse::<CacheSize>();
+        assert!(matches!(parse("250"), Ok(CacheSize::Crates(250))));
+        assert!(matches!(parse("512B"), Ok(CacheSize::Bytes(512))));
+        assert!(matches!(parse("1.5K"), Ok(CacheSize::Bytes(1536))));
+        assert!(matches!(parse("10GiB"), Ok(CacheSize::Bytes(x)) if x == 10 << 30));
+        assert!(parse("0").is_err());
+        assert!(parse("1.5").is_err());
+        assert!(parse("K").is_err());
+    }
+
+    #[test]
+    fn out_of_memory() {
+        assert!(is_out_of_memory(
+            "    Checking foo v1.0.0\nmemory allocation of 4294967296 bytes failed\n"
+        ));
+        assert!(is_out_of_memory("LLVM ERROR: out of memory\n"));
+        assert!(!is_out_of_memory(
+            "error: could not compile `foo` due to previous error\n"
+        ));
+    }
+
+    #[test]
+    fn empty_source() {
+        assert!(is_empty_source(""));
+        assert!(is_empty_source(
+            "//! Generated by the build script.\n\
+            #![no_std]\n\
+            /* nothing\n here */\n"
+        ));
+        assert!(is_empty_source("#![doc = \"https://docs.rs/foo\"]\n"));
+        assert!(!is_empty_source("// A library\npub fn foo() {}\n"));
+        assert!(!is_empty_source(
+            "include!(concat!(env!(\"OUT_DIR\"), \"/gen.rs\"));\n"
+        ));
+        assert!(!is_empty_source("/* unterminated"));
+    }
+
+    #[test]
+    fn profile_names() {
+        assert!(check_profile_name("release").is_ok());
+        assert!(check_profile_name("fast-check_2").is_ok());
+        assert!(check_profile_name("").is_err());
+        assert!(check_profile_name("a b").is_err());
+        assert!(check_profile_name("debug").is_err());
+        assert_eq!(profile_dir_name("dev"), "debug");
+        assert_eq!(profile_dir_name("release"), "release");
+        assert_eq!(profile_dir_name("fast-check"), "fast-check");
+    }
+
+    #[test]
+    fn lockfile_deps() {
+        let deps = parse_lockfile_deps(
+            r#"
+            version = 3
+
+            [[package]]
+            name = "foo"
+            version = "0.1.0"
+            dependencies = ["serde"]
+
+            [[package]]
+            name = "serde"
+            version = "1.0.140"
+            source = "registry+https://github.com/rust-lang/crates.io-index"
+            checksum = "fc855a42c7967b7c369eb5860f7164ef1f6f81c20c7cc1141f2a604e18723b03"
+
+            [[package]]
+            name = "bar"
+            version = "0.2.0"
+            source = "git+https://github.com/bar/bar#0123456789abcdef"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            deps,
+            [
+                ("bar".to_owned(), "0.2.0".to_owned()),
+                ("serde".to_owned(), "1.0.140".to_owned())
+            ]
+        );
+        assert!(parse_lockfile_deps("[[package]\n").is_none());
+    }
+
+    #[test]
+    fn manifest_package_strings() {
+        let manifest = toml::from_str(
+            r#"
+            [package]
+            categories = ["gui", "rendering::engine"]
+            keywords = ["ui", 5]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            package_strings(&manifest, "categories"),
+            ["gui", "rendering::engine"]
+        );
+        assert_eq!(package_strings(&manifest, "keywords"), ["ui"]);
+        assert!(package_strings(&manifest, "authors").is_empty());
+    }
+
+    #[test]
+    fn main_target_dirs() {
+        let manifest = toml::from_str(
+            r#"
+            package.autobins = false
+            [[bin]]
+            name = "foo"
+            path = "tests/foo.rs"
+            [[test]]
+            name = "bar"
+            path = "benches/bar.rs"
+            "#,
+        )
+        .unwrap();
+        assert!(has_main_target_in(&manifest, "tests"));
+        assert!(!has_main_target_in(&manifest, "benches"));
+        let manifest = toml::from_str("[lib]\npath = \"examples/lib.rs\"\n").unwrap();
+        assert!(has_main_target_in(&manifest, "examples"));
+        assert!(!has_main_target_in(&manifest, "ex"));
+    }
+
+    #[test]
+    fn remove_path_deps_none() {
+        let mut manifest = toml::from_str(
+            r#"
+            [dependencies]
+            foo = "1.0"
+            bar = { version = "0.1", features = ["baz"] }
+            "#,
+        )
+        .unwrap();
+        assert!(!remove_manifest_path_deps(&mut manifest));
+    }
+
+    #[test]
+    fn error_signatures() {
+        let mut errors = ErrorSignatures::new();
+        errors.add(
+            "error running clippy (exit status: 101):\n\
+            error: failed to run custom build command for `openssl-sys v0.9.72`\n\
+            \n\
+            Caused by:\n  process didn't exit successfully: `/tmp/.tmpabc/target/debug/build/openssl-sys-123/build-script-main`",
+        );
+        errors.add(
+            "error running clippy (exit status: 101):\n\
+            error: failed to run custom build command for `openssl-sys v0.9.80`\n",
+        );
+        errors.add("error[E0425]: cannot find value `x` in this scope\n --> /tmp/.tmpdef/foo-0.1.0/src/lib.rs:1:1\n");
+        errors.add("error: could not find `Cargo.toml` in `/tmp/.tmpabc/foo-0.1.0` or any parent directory\n");
+        errors.add("error: expected one and/or two\n");
+        assert_eq!(
+            errors.most_common(3),
+            [
+                (
+                    "error: failed to run custom build command for `openssl-sys <version>`",
+                    2
+                ),
+                (
+                    "error: could not find `Cargo.toml` in `<path>` or any parent directory",
+                    1
+                ),
+                ("error: expected one and/or two", 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn find_crates_normalized_names() {
+        let dir = temp_dir::TempDir::new().unwrap();
+        for name in [
+            "Foo_Bar-1.0.0",
+            "foo-bar-1.1.0",
+            "Foo_Bar-1.2.0-beta.1",
+            "baz-0.1.0",
+        ] {
+            fs::File::create(dir.path().join(format!("{}.crate", name))).unwrap();
+        }
+        let mut crates = find_crates(&[dir.path().into()], &mut Vec::new())
+            .unwrap()
+            .iter()
+            .flat_map(|krate| krate.iter_ids().map(|id| id.to_string()))
+            .collect::<Vec<_>>();
+        crates.sort();
+        assert_eq!(
+            crates,
+            ["Foo_Bar-1.2.0-beta.1", "baz-0.1.0", "foo-bar-1.1.0"]
+        );
+    }
+
+    #[test]
+    fn lint_glob() {
+        let re = glob_regex("clippy::needless_*");
+        assert!(re.is_match("clippy::needless_return"));
+        assert!(re.is_match("clippy::needless_"));
+        assert!(!re.is_match("clippy::not_needless_return"));
+        let re = glob_regex("clippy::as_?tr");
+        assert!(re.is_match("clippy::as_ptr"));
+        assert!(!re.is_match("clippy::as_pptr"));
+        assert!(!glob_regex("clippy::a.b").is_match("clippy::axb"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn filter_command() {
+        let filter = MessageFilter {
+            regex: None,
+            command: Some("grep -q unsafe"),
+        };
+        assert!(filter.accepts("warning: in an `unsafe` block").unwrap());
+        assert!(!filter.accepts("warning: unused variable").unwrap());
+        let re = regex::Regex::new("block").unwrap();
+        let filter = MessageFilter {
+            regex: Some(&re),
+            ..filter
+        };
+        assert!(!filter.accepts("warning: unsafe fn").unwrap());
+        assert!(MessageFilter::default().accepts("").unwrap());
+    }
+
+    #[test]
+    fn other_clippy_command() {
+        let toolchain = Toolchain::parse(
+            &toml::from_str("[toolchain]\nchannel = \"nightly-2022-06-30\"\n").unwrap(),
+        )
+        .unwrap();
+        let clippy = ClippyArgs::Source {
+            manifest: "--manifest-path=clippy/Cargo.toml".into(),
+            toolchain,
+        };
+        let mut command = clippy.run_command();
+        let clippy_arg_count = command.get_args().len();
+        command
+            .args(["--target-dir", "target", "--", "--warn", "clippy::foo"])
+            .args(["-C", "incremental=incremental/foo-0.1.0"])
+            .env("FOO", "1");
+
+        let other = with_other_clippy(
+            &command,
+            clippy_arg_count,
+            &ClippyArgs::Rustup {
+                version: String::new(),
+            },
+            Path::new("other"),
+            "incremental=other-incremental/foo-0.1.0".as_ref(),
+        );
+        assert_eq!(other.get_program(), "cargo-clippy");
+        assert_eq!(
+            other.get_args().collect::<Vec<_>>(),
+            [
+                "--target-dir",
+                "other",
+                "--",
+                "--warn",
+                "clippy::foo",
+                "-C",
+                "incremental=other-incremental/foo-0.1.0"
+            ]
+        );
+        assert_eq!(
+            other.get_envs().collect::<Vec<_>>(),
+            [("FOO".as_ref(), Some("1".as_ref()))]
+        );
+    }
+
+    #[test]
+    fn qualify_lint_names() {
+        let info = || LintInfo {
+            default_level: "warn".into(),
+            group: None,
+            description: String::new(),
+        };
+        let lint_info = HashMap::from([
+            ("unused_imports".to_owned(), info()),
+            ("clippy::let_unit_value".to_owned(), info()),
+        ]);
+        assert_eq!(
+            qualify_lint_name("unused_imports".into(), &lint_info),
+            "unused_imports"
+        );
+        assert_eq!(
+            qualify_lint_name("let_unit_value".into(), &lint_info),
+            "clippy::let_unit_value"
+        );
+        assert_eq!(
+            qualify_lint_name("clippy::new_lint".into(), &lint_info),
+            "clippy::new_lint"
+        );
+        assert_eq!(
+            qualify_lint_name("unused_imports".into(), &HashMap::new()),
+            "clippy::unused_imports"
+        );
+    }
+
+    #[test]
+    fn platform_errors() {
+        assert!(is_platform_error(
+            None,
+            "error: Only MacOSX or iOS is supported"
+        ));
+        assert!(is_platform_error(None, "error: only MACOS or IOS targets"));
+        assert!(is_platform_error(None, "error: MacOS or iOS only"));
+        assert!(is_platform_error(
+            None,
+            "error: This crate was renamed to `bar`"
+        ));
+        assert!(is_platform_error(
+            Some("E0432"),
+            "error[E0432]: unresolved import\n  | use WinAPI::um;"
+        ));
+        assert!(is_platform_error(
+            Some("E0455"),
+            "error[E0455]: link kind `Framework` is only supported on Apple targets"
+        ));
+        assert!(is_platform_error(
+            Some("E0412"),
+            "error[E0412]: ...\n  | use std::os::windows::io::RawHandle;"
+        ));
+        assert!(!is_platform_error(
+            Some("E0425"),
+            "error[E0425]: cannot find value `macos` in this scope"
+        ));
+        assert!(!is_platform_error(
+            Some("E0599"),
+            "error[E0599]: is deprecated"
+        ));
+    }
+
+    #[test]
+    fn lint_codes() {
+        assert!(is_lint_code("unused_variables"));
+        assert!(is_lint_code("clippy::needless_return"));
+        assert!(!is_lint_code("E0308"));
+    }
+
+    #[test]
+    fn feature_errors() {
+        assert!(is_feature_error(
+            "E0658",
+            "error[E0658]: use of unstable library feature `foo`"
+        ));
+        assert!(is_feature_error(
+            "E0635",
+            "error[E0635]: unknown feature `foo`"
+        ));
+        assert!(!is_feature_error("E0635", "error[E0635]: something else"));
+        assert!(!is_feature_error(
+            "E0425",
+            "error[E0425]: cannot find value `x` in this scope"
+        ));
+    }
+
+    #[test]
+    fn clear_target_keeps_shared_deps() {
+        let dir = temp_dir::TempDir::new().unwrap();
+        let profile_dir = dir.path().join("debug");
+        for path in [
+            "deps/libserde-0123456789abcdef.rmeta",
+            "deps/libfoo-0123456789abcdef.rmeta",
+            ".fingerprint/serde-0123456789abcdef/lib-serde",
+            "incremental/foo-0123456789abcdef/s-abc/dep-graph.bin",
+            "libfoo.rlib",
+        ] {
+            let path = profile_dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        clear_target_dir(&profile_dir, &HashSet::from(["serde".to_owned()]));
+        assert!(profile_dir
+            .join("deps/libserde-0123456789abcdef.rmeta")
+            .is_file());
+        assert!(profile_dir
+            .join(".fingerprint/serde-0123456789abcdef/lib-serde")
+            .is_file());
+        assert!(!profile_dir
+            .join("deps/libfoo-0123456789abcdef.rmeta")
+            .exists());
+        assert!(!profile_dir.join("incremental").exists());
+        assert!(!profile_dir.join("libfoo.rlib").exists());
+    }
+
+    #[test]
+    fn local_packages() {
+        let id = |repr: &str| cargo_metadata::PackageId { repr: repr.into() };
+        assert!(is_local_package(&id(
+            "path+file:///tmp/foo-0.1.0#foo@0.1.0"
+        )));
+        assert!(is_local_package(&id(
+            "foo 0.1.0 (path+file:///tmp/foo-0.1.0)"
+        )));
+        assert!(!is_local_package(&id(
+            "registry+https://github.com/rust-lang/crates.io-index#serde@1.0.0"
+        )));
+        assert!(!is_local_package(&id(
+            "serde 1.0.0 (registry+https://github.com/rust-lang/crates.io-index)"
+        )));
+    }
+
+    #[test]
+    fn detect_ice() {
+        assert!(is_ice(
+            "error: internal compiler error: compiler/rustc_middle/src/ty/mod.rs:1234:5: \
+            no entry found for key\n"
+        ));
+        assert!(is_ice(
+            "thread 'rustc' panicked at 'called `Option::unwrap()` on a `None` value', \
+            src/tools/clippy/clippy_lints/src/foo.rs:10:5\n"
+        ));
+        assert!(is_ice(
+            "error: the compiler unexpectedly panicked. this is a bug.\n\n\
+            note: we would appreciate a bug report\n"
+        ));
+        assert!(is_ice(
+            "error: delayed span bug: broken MIR in DefId(0:5 ~ foo[1234]::bar)\n"
+        ));
+        assert!(!is_ice(
+            "error[E0425]: cannot find value `x` in this scope\n --> src/lib.rs:1:1\n"
+        ));
+        assert!(!is_ice(
+            "error: could not compile `foo` due to previous error\n"
+        ));
+        assert!(!is_ice(
+            "warning: this fixes a delayed bug and an internal compiler error\n"
+        ));
+    }
+
+    #[test]
+    fn find_crates_multiple_dirs() {
+        let dirs = [
+            temp_dir::TempDir::new().unwrap(),
+            temp_dir::TempDir::new().unwrap(),
+        ];
+        for (dir, names) in dirs.iter().zip([
+            &["foo-1.0.0", "rustc-ap-foo-1.0.0"][..],
+            &["foo-1.0.0", "rustc-ap-foo-1.0.0", "bar-0.1.0"],
+        ]) {
+            for name in names {
+                fs::File::create(dir.path().join(format!("{}.crate", name))).unwrap();
+            }
+        }
+        let dirs: Vec<_> = dirs.iter().map(|dir| dir.path().to_owned()).collect();
+        let mut skipped = Vec::new();
+        let mut crates = find_crates(&dirs, &mut skipped)
+            .unwrap()
+            .iter()
+            .flat_map(|krate| krate.iter_ids().map(|id| id.to_string()))
+            .collect::<Vec<_>>();
+        crates.sort();
+        assert_eq!(crates, ["bar-0.1.0", "foo-1.0.0"]);
+        assert_eq!(skipped.len(), 1);
+    }
+
+    #[test]
+    fn find_crates_matches_sequential() {
+        let dir = temp_dir::TempDir::new().unwrap();
+        let mut files = Vec::new();
+        for i in 0..20 {
+            // Older versions use a different spelling of the name.
+            for (name, version) in [
+                ("crate_", "0.1.0"),
+                ("crate-", "0.2.0"),
+                ("crate-", "1.0.0-beta.1"),
+                ("crate_", "1.0.0-rc.1"),
+                ("crate_", "0.1.1-alpha.1"),
+            ] {
+                let file = format!("{}{}-{}.crate", name, i, version);
+                fs::File::create(dir.path().join(&file)).unwrap();
+                files.push(file.into());
+            }
+        }
+        let ids = |crates: Vec<CachedCrate>| {
+            let mut ids = crates
+                .iter()
+                .flat_map(|krate| krate.iter_ids().map(|id| id.to_string()))
+                .collect::<Vec<_>>();
+            ids.sort();
+            ids
+        };
+        let parallel = ids(find_crates(&[dir.path().into()], &mut Vec::new()).unwrap());
+        let sequential = ids(parse_crate_files(&files).0.into_values().collect());
+        assert_eq!(parallel, sequential);
+        assert_eq!(parallel.len(), 60);
+        assert_eq!(
+            parallel
+                .iter()
+                .filter(|id| id.starts_with("crate_"))
+                .count(),
+            20
+        );
+    }
+
+    #[test]
+    fn merge_chunks_keeps_file_names() {
+        let merge = |chunks: [&str; 2]| {
+            let results = chunks
+                .iter()
+                .map(|&file| parse_crate_files(&[file.into()]))
+                .collect();
+            let crates = merge_crate_versions(results, &mut Vec::new());
+            crates["foo_bar"]
+                .iter_ids()
+                .map(|id| id.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            merge(["foo_bar-1.0.0.crate", "foo-bar-1.1.0.crate"]),
+            ["foo-bar-1.1.0"]
+        );
+        assert_eq!(
+            merge(["foo-bar-1.1.0.crate", "foo_bar-1.0.0.crate"]),
+            ["foo-bar-1.1.0"]
+        );
+        assert_eq!(
+            merge(["foo_bar-1.0.0.crate", "foo-bar-1.1.0-beta.1.crate"]),
+            ["foo_bar-1.0.0", "foo-bar-1.1.0-beta.1"]
+        );
+    }
+
+    /// A diagnostic with suggestions replacing lines 2-4 with two lines, and removing line 10.
+    fn suggestion_diagnostic() -> Diagnostic {
+        let span = |line_start: usize, line_end: usize, replacement: Option<&str>| {
+            serde_json::json!({
+                "file_name": "src/lib.rs",
+                "byte_start": 0,
+                "byte_end": 0,
+                "line_start": line_start,
+                "line_end": line_end,
+                "column_start": 1,
+                "column_end": 1,
+                "is_primary": true,
+                "text": [],
+                "label": null,
+                "suggested_replacement": replacement,
+                "suggestion_applicability": null,
+                "expansion": null,
+            })
+        };
+        let diagnostic = |spans, children| {
+            serde_json::json!({
+                "message": "",
+                "code": null,
+                "level": "warning",
+                "spans": spans,
+                "children": children,
+                "rendered": null,
+            })
+        };
+        let d = diagnostic(
+            vec![span(1, 1, None)],
+            vec![
+                diagnostic(vec![span(2, 4, Some("foo\nbar"))], vec![]),
+                diagnostic(vec![span(10, 10, Some(""))], vec![]),
+            ],
+        );
+        serde_json::from_value(d).unwrap()
+    }
+
+    #[test]
+    fn suggestion_churn() {
+        let churn = Churn::of_diagnostic(&suggestion_diagnostic());
+        assert_eq!((churn.added, churn.removed), (2, 4));
+    }
+
+    #[test]
+    fn suggestion_samples() {
+        let d = suggestion_diagnostic();
+        let mut replacements = Vec::new();
+        suggested_replacements(&d, &mut replacements);
+        assert_eq!(replacements, ["foo\nbar", ""]);
+
+        let mut samples = SuggestionSamples::default();
+        for replacement in ["a", "b", "a", "c"] {
+            samples.add(replacement.into(), 2);
+        }
+        assert_eq!(samples.samples, ["a", "b"]);
+        assert!(samples.truncated);
+    }
+
+    #[test]
+    fn hash_crate_source() {
+        let dirs = [(); 3].map(|()| temp_dir::TempDir::new().unwrap());
+        for (i, dir) in dirs.iter().enumerate() {
+            fs::create_dir(dir.path().join("src")).unwrap();
+            fs::write(
+                dir.path().join("Cargo.toml"),
+                format!("version = \"0.1.{}\"", i),
+            )
+            .unwrap();
+            let src = if i == 2 { "fn f() {}" } else { "" };
+            fs::write(dir.path().join("src").join("lib.rs"), src).unwrap();
+        }
+        let hashes = dirs.map(|dir| hash_source(dir.path()).unwrap());
+        assert_eq!(hashes[0], hashes[1]);
+        assert_ne!(hashes[0], hashes[2]);
+        // The hash must not change between runs.
+        assert_eq!(hashes[2], 0xb73b_b064_eaf8_c82c);
+
+        // Hashing while extracting gives the same result, regardless of the order of the entries.
+        let dir = temp_dir::TempDir::new().unwrap();
+        let file = dir.path().join("foo-0.1.2.crate");
+        let mut builder = tar::Builder::new(GzEncoder::new(
+            fs::File::create(&file).unwrap(),
+            Compression::default(),
+        ));
+        for (path, contents) in [
+            ("foo-0.1.2/src/lib.rs", "fn f() {}"),
+            ("foo-0.1.2/Cargo.toml", "version = \"0.1.2\""),
+        ] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            builder
+                .append_data(&mut header, path, contents.as_bytes())
+                .unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+        let (root, hash) = extract_crate(&file, &dir.path().join("out"), true).unwrap();
+        assert_eq!(hash, Some(hashes[2]));
+        assert_eq!(
+            fs::read_to_string(root.join("src").join("lib.rs")).unwrap(),
+            "fn f() {}"
+        );
+    }
+
+    /// Writes a crate whose symlinks only leave the extraction directory when followed in order.
+    fn chained_symlink_crate(file: &Path) {
+        let mut builder = tar::Builder::new(GzEncoder::new(
+            fs::File::create(file).unwrap(),
+            Compression::default(),
+        ));
+        for (path, link) in [("foo-0.1.0/a", ".."), ("foo-0.1.0/a/b", "../..")] {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(tar::EntryType::Symlink);
+            header.set_size(0);
+            builder.append_link(&mut header, path, link).unwrap();
+        }
+        let mut header = tar::Header::new_gnu();
+        header.set_size(0);
+        header.set_mode(0o644);
+        builder
+            .append_data(&mut header, "foo-0.1.0/a/b/PWNED.txt", &[][..])
+            .unwrap();
+        builder.into_inner().unwrap().finish().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn extract_hashed_chained_symlinks() {
+        let dir = temp_dir::TempDir::new().unwrap();
+        let file = dir.path().join("foo-0.1.0.crate");
+        chained_symlink_crate(&file);
+        let target = dir.path().join("x").join("y").join("out");
+        let _ = extract_crate(&file, &target, true);
+        for parent in [dir.path().join("x"), dir.path().join("x").join("y")] {
+            assert!(!parent.join("PWNED.txt").exists());
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn extract_chained_symlinks() {
+        let dir = temp_dir::TempDir::new().unwrap();
+        let file = dir.path().join("foo-0.1.0.crate");
+        chained_symlink_crate(&file);
+        let target = dir.path().join("out");
+        extract_crate(&file, &target, false).unwrap();
+        // `a` points at `target`, but `b` would leave it.
+        assert!(fs::symlink_metadata(target.join("foo-0.1.0").join("a"))
+            .unwrap()
+            .is_symlink());
+        assert!(fs::symlink_metadata(target.join("b")).unwrap().is_dir());
+        assert!(target.join("b").join("PWNED.txt").is_file());
+        assert!(!dir.path().join("PWNED.txt").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn copy_dir_skips_symlinks() {
+        let from = temp_dir::TempDir::new().unwrap();
+        let to = temp_dir::TempDir::new().unwrap();
+        fs::create_dir(from.path().join("src")).unwrap();
+        fs::write(from.path().join("src").join("lib.rs"), "").unwrap();
+        std::os::unix::fs::symlink(from.path(), from.path().join("src").join("parent")).unwrap();
+        copy_dir(from.path(), to.path()).unwrap();
+        assert!(to.path().join("src").join("lib.rs").is_file());
+        assert!(!to.path().join("src").join("parent").exists());
+    }
+
+    #[test]
+    fn toolchain_components() {
+        let toolchain = Toolchain::parse(
+            &toml::from_str(
+                r#"
+                [toolchain]
+                channel = "nightly-2022-06-30"
+                components = ["cargo", "llvm-tools-preview", "rust-src", "rustc-dev"]
+                "#,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(toolchain.channel, "nightly-2022-06-30");
+        assert_eq!(toolchain.profile, None);
+
+        let installed = "cargo-x86_64-unknown-linux-gnu\n\
+            llvm-tools-x86_64-unknown-linux-gnu\n\
+            rust-src\n\
+            rustc-x86_64-unknown-linux-gnu\n";
+        let missing: Vec<_> = toolchain
+            .components
+            .iter()
+            .filter(|c| !is_component_installed(installed, c))
+            .collect();
+        assert_eq!(missing, ["rustc-dev"]);
+    }
+
+    #[test]
+    fn extract_renamed_crate() {
+        let dir = temp_dir::TempDir::new().unwrap();
+        let file = dir.path().join("foo-0.1.0.crate");
+        let mut builder = tar::Builder::new(GzEncoder::new(
+            fs::File::create(&file).unwrap(),
+            Compression::default(),
+        ));
+        let contents = b"[package]\nname = \"Foo\"\n";
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        builder
+            .append_data(&mut header, "Foo-0.1.0/Cargo.toml", &contents[..])
+            .unwrap();
+        builder.into_inner().unwrap().finish().unwrap();
+
+        let target = dir.path().join("out");
+        let (root, _) = extract_crate(&file, &target, false).unwrap();
+        assert_eq!(root, target.join("Foo-0.1.0"));
+        assert!(root.join("Cargo.toml").is_file());
+    }
+
+    #[test]
+    fn build_script_error() {
+        let stderr = "error: failed to run custom build command for `openssl-sys v0.9.72`\n\
+            \n\
+            Caused by:\n\
+            \x20 process didn't exit successfully: `/tmp/target/build-script-main` (exit status: 101)\n\
+            \x20 --- stdout\n\
+            \x20 cargo:rerun-if-env-changed=OPENSSL_DIR\n\
+            \n\
+            \x20 --- stderr\n\
+            \x20 thread 'main' panicked at build/find_normal.rs:190:5:\n\
+            \x20 Could not find directory of OpenSSL installation\n\
+            \x20 note: run with `RUST_BACKTRACE=1` to display a backtrace\n";
+        assert_eq!(
+            parse_build_script_error(stderr),
+            Some((
+                "openssl-sys v0.9.72",
+                "thread 'main' panicked at build/find_normal.rs:190:5: \
+                Could not find directory of OpenSSL installation"
+                    .into()
+            ))
+        );
+        assert_eq!(
+            parse_build_script_error("error: could not compile `foo`\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn histogram_buckets() {
+        let mut histogram = Histogram::default();
+        for count in [1, 1, 2, 5, 6, 20, 21, 500] {
+            histogram.add(count);
+        }
+        let buckets: Vec<_> = histogram.buckets().collect();
+        assert_eq!(
+            buckets,
+            [
+                ("1".to_owned(), 2),
+                ("2-5".to_owned(), 2),
+                ("6-20".to_owned(), 2),
+                ("21+".to_owned(), 2)
+            ]
+        );
+    }
+
+    #[test]
+    fn normalize_relative_paths() {
+        assert_eq!(
+            normalize_relative(Path::new("foo-0.1.0/src/../Cargo.toml")),
+            Some(PathBuf::from("foo-0.1.0/Cargo.toml"))
+        );
+        assert_eq!(
+            normalize_relative(Path::new("./foo-0.1.0/src/lib.rs")),
+            Some(PathBuf::from("foo-0.1.0/src/lib.rs"))
+        );
+        assert_eq!(normalize_relative(Path::new("foo-0.1.0/../../x")), None);
+        assert_eq!(normalize_relative(Path::new("../x")), None);
+        assert_eq!(normalize_relative(Path::new("/etc/passwd")), None);
+    }
+
+    #[test]
+    fn prepared_source() {
+        let dir = temp_dir::TempDir::new().unwrap();
+        fs::create_dir(dir.path().join("tests")).unwrap();
+        fs::write(dir.path().join("Cargo.toml"), "[package]\nname = \"foo\"\n").unwrap();
+        assert_eq!(
+            prepared_source_script(dir.path(), true),
+            "rm -rf .cargo/config\n\
+            rm -rf benches\n\
+            rm -rf examples\n\
+            rm -rf Cargo.lock\n\
+            cat > Cargo.toml <<'CLIPPY_LINT_TEST_EOF'\n\
+            [package]\n\
+            name = \"foo\"\n\
+            CLIPPY_LINT_TEST_EOF"
+        );
+    }
+
+    #[test]
+    fn ice_repro_script() {
+        let manifest = Path::new("/tmp/x/foo-0.1.0/Cargo.toml");
+        let mut command = std::process::Command::new("cargo-clippy");
+        command
+            .env("RUSTFLAGS", "-C debuginfo=0")
+            .arg("--manifest-path")
+            .arg(manifest)
+            .args(["--message-format=json", "--target-dir", "/tmp/target", "--"])
+            .args(["--cap-lints", "warn", "--warn", "clippy::foo's"]);
+        assert_eq!(
+            repro_script(
+                &command,
+                "tar -xzf foo-0.1.0.crate && cd foo-0.1.0",
+                manifest
+            ),
+            "tar -xzf foo-0.1.0.crate && cd foo-0.1.0\n\
+            'RUSTFLAGS=-C debuginfo=0' cargo-clippy --manifest-path Cargo.toml -- --cap-lints warn \
+            --warn 'clippy::foo'\\''s'\n"
+        );
+    }
+}
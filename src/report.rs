@@ -0,0 +1,842 @@
+//! Output formats for the report file.
+
+use crate::{
+    compare::{CrateDiff, DiffTotals},
+    lint_info::LintInfo,
+    version_diff::VersionDiff,
+    Churn, CrateStatus, Histogram, SkipReason, SuggestionSamples, TimingSummary, Toolchain,
+};
+use serde_json::{json, Value};
+use std::{
+    collections::{HashMap, HashSet},
+    io::{self, Write},
+    str,
+};
+
+/// The format of the report file.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    Text,
+    Json,
+    Markdown,
+    /// One JSON object per line.
+    Ndjson,
+}
+impl ReportFormat {
+    /// Gets the file extension used for the default report name.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Text => "txt",
+            Self::Json => "json",
+            Self::Markdown => "md",
+            Self::Ndjson => "ndjson",
+        }
+    }
+
+    /// Creates a report writer for the format.
+    pub fn writer(self, w: impl Write + 'static) -> Box<dyn ReportWriter> {
+        match self {
+            Self::Text => Box::new(TextWriter(w)),
+            Self::Json => Box::new(JsonWriter {
+                w,
+                crates: Vec::new(),
+            }),
+            Self::Markdown => Box::new(MarkdownWriter(w)),
+            Self::Ndjson => Box::new(NdjsonWriter(w)),
+        }
+    }
+}
+impl str::FromStr for ReportFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            "markdown" => Ok(Self::Markdown),
+            "ndjson" => Ok(Self::Ndjson),
+            _ => Err(format!(
+                "unknown report format `{}`, expected one of `text`, `json`, `markdown` or `ndjson`",
+                s
+            )),
+        }
+    }
+}
+
+/// The results of checking a single crate.
+pub struct CrateReport<'a> {
+    pub name: &'a str,
+    pub downloads: Option<u64>,
+    pub fix_msg: Option<&'a str>,
+    /// The lint name and rendered message of each lint emitted. Messages for the same lint must be
+    /// next to each other.
+    pub lint_msgs: &'a [(String, String)],
+    pub ambiguous_dep: Option<&'a str>,
+    /// The error which stopped the build, if any. Any warnings may be incomplete.
+    pub err_msg: Option<&'a str>,
+    pub ice_msg: Option<&'a str>,
+    /// A shell script which reproduces the ICE.
+    pub ice_repro: Option<&'a str>,
+    /// The previously checked crate with identical source.
+    pub duplicate_of: Option<&'a str>,
+    /// The crate has no stable release, so a pre-release was checked.
+    pub prerelease_only: bool,
+    /// A hash of the crate's source which is stable across runs and versions.
+    pub content_hash: Option<u64>,
+    /// The name and version of each dependency the crate was built with. Only set with
+    /// `--record-deps`.
+    pub resolved_deps: Option<&'a [(String, String)]>,
+    /// The categories and keywords declared in the crate's manifest.
+    pub categories: &'a [String],
+    pub keywords: &'a [String],
+    /// The differences from the compared clippy, if there are any.
+    pub diff: Option<&'a CrateDiff>,
+}
+impl CrateReport<'_> {
+    /// Iterates over the messages for each lint.
+    fn lint_groups(&self) -> impl Iterator<Item = &[(String, String)]> {
+        self.lint_msgs.chunk_by(|x, y| x.0 == y.0)
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "crate": self.name,
+            "downloads": self.downloads,
+            "fix_msg": self.fix_msg,
+            "warnings": self
+                .lint_msgs
+                .iter()
+                .map(|(lint, msg)| json!({ "lint": lint, "message": msg }))
+                .collect::<Vec<_>>(),
+            "ambiguous_dep": self.ambiguous_dep,
+            "error": self.err_msg,
+            "ice": self.ice_msg,
+            "ice_repro": self.ice_repro,
+            "duplicate_of": self.duplicate_of,
+            "prerelease_only": self.prerelease_only,
+            "content_hash": self.content_hash.map(|hash| format!("{:016x}", hash)),
+            "resolved_deps": self.resolved_deps.map(|deps| {
+                deps.iter()
+                    .map(|(name, version)| json!({ "name": name, "version": version }))
+                    .collect::<Vec<_>>()
+            }),
+            "categories": self.categories,
+            "keywords": self.keywords,
+            "diff": self.diff.map(|diff| {
+                let msgs = |msgs: &[(String, String)]| {
+                    msgs.iter()
+                        .map(|(lint, msg)| json!({ "lint": lint, "message": msg }))
+                        .collect::<Vec<_>>()
+                };
+                json!({
+                    "added": msgs(&diff.added),
+                    "removed": msgs(&diff.removed),
+                    "new_ice": diff.new_ice,
+                    "fixed_ice": diff.fixed_ice,
+                })
+            }),
+        })
+    }
+}
+
+/// The summary of the whole run.
+pub struct Summary<'a> {
+    /// The toolchain clippy was built with, if it was built from source.
+    pub toolchain: Option<&'a Toolchain>,
+    /// The version of clippy, if it was installed by rustup.
+    pub clippy_version: Option<&'a str>,
+    /// Every crate which was checked, including those without results.
+    pub checked: &'a HashSet<String>,
+    /// The status and download count of each crate with any results.
+    pub crates: &'a [(String, CrateStatus, Option<u64>)],
+    pub lints: &'a HashMap<String, usize>,
+    /// The default level and group of each clippy lint. Empty if it couldn't be queried.
+    pub lint_info: &'a HashMap<String, LintInfo>,
+    /// How many times each lint fired per crate.
+    pub histograms: &'a HashMap<String, Histogram>,
+    /// The number of lines each lint's suggestions would change. Only set when checking fixes.
+    pub fix_churn: Option<&'a HashMap<String, Churn>>,
+    /// Lints which fired a different number of times on different versions of a crate.
+    pub version_diffs: &'a [VersionDiff],
+    /// Crates which only built a non-rust library, and had `rlib` added to their crate types.
+    pub added_rlib: &'a [String],
+    /// The number of messages of each lint left out of the report by `--max-per-lint`.
+    pub omitted: &'a HashMap<String, usize>,
+    /// The distinct replacements suggested by each lint. Empty unless `--suggestion-samples` was
+    /// given.
+    pub suggestion_samples: &'a HashMap<String, SuggestionSamples>,
+    /// The differences from the compared clippy across all crates, if one was given.
+    pub diff_totals: Option<&'a DiffTotals>,
+    pub skipped: &'a [(String, SkipReason)],
+    /// The most common error signatures and how many crates failed with each.
+    pub common_errors: &'a [(&'a str, usize)],
+    pub timing: &'a TimingSummary,
+}
+impl Summary<'_> {
+    /// Gets the histogram for each lint which fired, sorted by lint name.
+    fn sorted_histograms(&self) -> Vec<(&str, &Histogram)> {
+        let mut res: Vec<_> = self
+            .histograms
+            .iter()
+            .map(|(lint, h)| (lint.as_str(), h))
+            .collect();
+        res.sort_by(|x, y| x.0.cmp(y.0));
+        res
+    }
+
+    /// Gets the tested lints which never fired, sorted by name.
+    fn unfired_lints(&self) -> Vec<&str> {
+        let mut res: Vec<_> = self
+            .lints
+            .iter()
+            .filter(|&(_, &count)| count == 0)
+            .map(|(lint, _)| lint.as_str())
+            .collect();
+        res.sort_unstable();
+        res
+    }
+
+    fn sorted_checked(&self) -> Vec<&str> {
+        let mut res: Vec<_> = self.checked.iter().map(String::as_str).collect();
+        res.sort_unstable();
+        res
+    }
+
+    /// Gets the number of omitted messages of each lint, sorted by lint name.
+    fn sorted_omitted(&self) -> Vec<(&str, usize)> {
+        let mut res: Vec<_> = self
+            .omitted
+            .iter()
+            .map(|(lint, &count)| (lint.as_str(), count))
+            .collect();
+        res.sort_by(|x, y| x.0.cmp(y.0));
+        res
+    }
+
+    /// Gets the suggestion samples of each lint, sorted by lint name.
+    fn sorted_suggestion_samples(&self) -> Vec<(&str, &SuggestionSamples)> {
+        let mut res: Vec<_> = self
+            .suggestion_samples
+            .iter()
+            .map(|(lint, samples)| (lint.as_str(), samples))
+            .collect();
+        res.sort_by(|x, y| x.0.cmp(y.0));
+        res
+    }
+
+    /// Gets the churn of each lint sorted by name, followed by the total churn.
+    fn sorted_churn(&self) -> Option<(Vec<(&str, Churn)>, Churn)> {
+        let churn = self.fix_churn?;
+        let mut lints: Vec<_> = churn.iter().map(|(l, &c)| (l.as_str(), c)).collect();
+        lints.sort_by(|x, y| x.0.cmp(y.0));
+        let total = lints.iter().fold(Churn::default(), |x, &(_, y)| x + y);
+        Some((lints, total))
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "toolchain": self.toolchain.map(|t| json!({
+                "channel": t.channel,
+                "components": t.components,
+                "profile": t.profile,
+            })),
+            "clippy_version": self.clippy_version,
+            "checked": self.sorted_checked(),
+            "crates": self
+                .crates
+                .iter()
+                .map(|(krate, status, downloads)| {
+                    json!({
+                        "crate": krate,
+                        "warnings": status.lint_count,
+                        "ice": status.ice,
+                        "denied": status.denied,
+                        "incomplete": status.incomplete,
+                        "failed": status.failed,
+                        "fix_failed": status.fix_failed,
+                        "ambiguous_dep": status.ambiguous_dep,
+                        "downloads": downloads,
+                    })
+                })
+                .collect::<Vec<_>>(),
+            "lints": self.lints,
+            "unfired_lints": self.unfired_lints(),
+            "lint_info": self
+                .lints
+                .keys()
+                .filter_map(|lint| {
+                    let info = self.lint_info.get(lint)?;
+                    Some((
+                        lint.clone(),
+                        json!({ "default_level": info.default_level, "group": info.group }),
+                    ))
+                })
+                .collect::<serde_json::Map<_, _>>(),
+            "histograms": self
+                .histograms
+                .iter()
+                .map(|(lint, h)| {
+                    let buckets = h
+                        .buckets()
+                        .map(|(label, count)| (label, count.into()))
+                        .collect::<serde_json::Map<_, _>>();
+                    (lint.clone(), Value::Object(buckets))
+                })
+                .collect::<serde_json::Map<_, _>>(),
+            "fix_churn": self.fix_churn.map(|churn| {
+                churn
+                    .iter()
+                    .map(|(lint, c)| {
+                        (lint.clone(), json!({ "added": c.added, "removed": c.removed }))
+                    })
+                    .collect::<serde_json::Map<_, _>>()
+            }),
+            "version_diffs": self
+                .version_diffs
+                .iter()
+                .map(|diff| json!({
+                    "crate": diff.krate,
+                    "lint": diff.lint,
+                    "counts": diff
+                        .counts
+                        .iter()
+                        .map(|(version, count)| json!({ "version": version, "count": count }))
+                        .collect::<Vec<_>>(),
+                }))
+                .collect::<Vec<_>>(),
+            "added_rlib": self.added_rlib,
+            "omitted": self.omitted,
+            "suggestion_samples": self
+                .suggestion_samples
+                .iter()
+                .map(|(lint, samples)| {
+                    (
+                        lint.clone(),
+                        json!({ "samples": samples.samples, "truncated": samples.truncated }),
+                    )
+                })
+                .collect::<serde_json::Map<_, _>>(),
+            "diff": self.diff_totals.map(|totals| json!({
+                "lints": totals
+                    .lints
+                    .iter()
+                    .map(|(lint, (added, removed))| {
+                        (lint.clone(), json!({ "added": added, "removed": removed }))
+                    })
+                    .collect::<serde_json::Map<_, _>>(),
+                "new_ices": totals.new_ices,
+                "fixed_ices": totals.fixed_ices,
+            })),
+            "skipped": self
+                .skipped
+                .iter()
+                .map(|(krate, reason)| json!({ "crate": krate, "reason": reason.to_string() }))
+                .collect::<Vec<_>>(),
+            "common_errors": self
+                .common_errors
+                .iter()
+                .map(|&(msg, count)| json!({ "message": msg, "count": count }))
+                .collect::<Vec<_>>(),
+            "timing": {
+                "total_secs": self.timing.total.as_secs_f64(),
+                "crates": self.timing.crate_count,
+                "per_crate_secs": self.timing.per_crate.map(|(min, median, max)| json!({
+                    "min": min.as_secs_f64(),
+                    "median": median.as_secs_f64(),
+                    "max": max.as_secs_f64(),
+                })),
+            },
+        })
+    }
+}
+
+/// Writes the report in a specific format.
+pub trait ReportWriter {
+    /// Writes the results for a single crate.
+    fn write_crate(&mut self, krate: &CrateReport) -> io::Result<()>;
+
+    /// Writes the summary. This is always the last thing written.
+    fn write_summary(&mut self, summary: &Summary) -> io::Result<()>;
+}
+
+/// Plain text output.
+struct TextWriter<W>(W);
+impl<W: Write> ReportWriter for TextWriter<W> {
+    fn write_crate(&mut self, krate: &CrateReport) -> io::Result<()> {
+        let w = &mut self.0;
+        if let Some(original) = krate.duplicate_of {
+            writeln!(w, "{}: same source as `{}`\n", krate.name, original)?;
+        }
+        if let Some(msg) = krate.fix_msg {
+            write!(w, "{}: Failed to apply fixes\n\n{}\n", krate.name, msg)?;
+        }
+        if !krate.lint_msgs.is_empty() {
+            write!(w, "{}: {} warnings", krate.name, krate.lint_msgs.len())?;
+            if let Some(downloads) = krate.downloads {
+                write!(w, " ({} downloads)", downloads)?;
+            }
+            if krate.prerelease_only {
+                write!(w, " (prerelease only)")?;
+            }
+            write!(w, "\n\n")?;
+            for group in krate.lint_groups() {
+                write!(w, "{}: {} occurrences\n\n", group[0].0, group.len())?;
+                for (_, m) in group {
+                    w.write_all(m.as_bytes())?;
+                }
+            }
+            writeln!(w)?;
+        }
+        if let Some(dep) = krate.ambiguous_dep {
+            writeln!(w, "{}: ambiguous_deps `{}`\n", krate.name, dep)?;
+        }
+        if let Some(msg) = krate.err_msg {
+            write!(w, "{}: build failed\n\n{}\n", krate.name, msg)?;
+        }
+        if let Some(msg) = krate.ice_msg {
+            write!(w, "{}: ICE\n\n{}\n", krate.name, msg)?;
+        }
+        if let Some(repro) = krate.ice_repro {
+            write!(w, "{}: reproduce with\n\n{}\n", krate.name, repro)?;
+        }
+        if let Some(diff) = krate.diff {
+            if diff.new_ice {
+                writeln!(w, "{}: new ICE\n", krate.name)?;
+            }
+            if diff.fixed_ice {
+                writeln!(w, "{}: fixed ICE\n", krate.name)?;
+            }
+            for (kind, msgs) in [("new", &diff.added), ("fixed", &diff.removed)] {
+                if !msgs.is_empty() {
+                    write!(w, "{}: {} {} warnings\n\n", krate.name, msgs.len(), kind)?;
+                    for (lint, m) in msgs {
+                        writeln!(w, "{}:", lint)?;
+                        w.write_all(m.as_bytes())?;
+                    }
+                    writeln!(w)?;
+                }
+            }
+        }
+        w.flush()
+    }
+
+    fn write_summary(&mut self, summary: &Summary) -> io::Result<()> {
+        let w = &mut self.0;
+        write!(w, "\nReport summary:\n\n")?;
+        if let Some(toolchain) = summary.toolchain {
+            write!(w, "Toolchain: {}\n\n", toolchain)?;
+        }
+        if let Some(version) = summary.clippy_version {
+            write!(w, "Clippy: {}\n\n", version)?;
+        }
+        for (krate, status, downloads) in summary.crates {
+            write!(w, "{}: {}", krate, status)?;
+            if let Some(downloads) = downloads {
+                write!(w, ", {} downloads", downloads)?;
+            }
+            writeln!(w)?;
+        }
+        writeln!(w)?;
+        for (lint, count) in summary.lints {
+            write!(w, "{}: {} occurrences", lint, count)?;
+            if let Some(info) = summary.lint_info.get(lint) {
+                write!(w, " ({} by default", info.default_level)?;
+                if let Some(group) = &info.group {
+                    write!(w, ", {}", group)?;
+                }
+                write!(w, ")")?;
+            }
+            writeln!(w)?;
+        }
+        let unfired = summary.unfired_lints();
+        if !unfired.is_empty() {
+            write!(w, "\nLints with no occurrences:\n\n")?;
+            for lint in unfired {
+                writeln!(w, "{}", lint)?;
+            }
+        }
+        let histograms = summary.sorted_histograms();
+        if !histograms.is_empty() {
+            write!(w, "\nCrates by occurrences per crate:\n\n")?;
+            for (lint, histogram) in histograms {
+                write!(w, "{}:", lint)?;
+                for (i, (label, count)) in histogram.buckets().enumerate() {
+                    write!(w, "{} {}: {}", if i == 0 { "" } else { "," }, label, count)?;
+                }
+                writeln!(w)?;
+            }
+        }
+        if let Some((lints, total)) = summary.sorted_churn() {
+            write!(w, "\nLines changed by fixes:\n\n")?;
+            for (lint, churn) in lints {
+                writeln!(w, "{}: {}", lint, churn)?;
+            }
+            writeln!(w, "total: {}", total)?;
+        }
+        if !summary.version_diffs.is_empty() {
+            write!(w, "\nLints which changed between versions:\n\n")?;
+            for diff in summary.version_diffs {
+                write!(w, "{} {}:", diff.krate, diff.lint)?;
+                for (i, (version, count)) in diff.counts.iter().enumerate() {
+                    write!(
+                        w,
+                        "{} {}: {}",
+                        if i == 0 { "" } else { "," },
+                        version,
+                        count
+                    )?;
+                }
+                writeln!(w)?;
+            }
+        }
+        if let Some(totals) = summary.diff_totals {
+            write!(w, "\nDifferences from the compared clippy:\n\n")?;
+            for (lint, added, removed) in totals.sorted_lints() {
+                writeln!(w, "{}: {} new, {} fixed", lint, added, removed)?;
+            }
+            for krate in &totals.new_ices {
+                writeln!(w, "{}: new ICE", krate)?;
+            }
+            for krate in &totals.fixed_ices {
+                writeln!(w, "{}: fixed ICE", krate)?;
+            }
+        }
+        if !summary.omitted.is_empty() {
+            write!(w, "\nMessages left out of the report:\n\n")?;
+            for (lint, count) in summary.sorted_omitted() {
+                writeln!(w, "{}: … and {} more", lint, count)?;
+            }
+        }
+        if !summary.suggestion_samples.is_empty() {
+            write!(w, "\nSuggested replacements:\n")?;
+            for (lint, samples) in summary.sorted_suggestion_samples() {
+                write!(w, "\n{}:\n", lint)?;
+                for sample in &samples.samples {
+                    writeln!(w, "  {:?}", sample)?;
+                }
+                if samples.truncated {
+                    writeln!(w, "  …")?;
+                }
+            }
+        }
+        if !summary.added_rlib.is_empty() {
+            write!(
+                w,
+                "\nCrates checked with `rlib` added to their crate types:\n\n"
+            )?;
+            for krate in summary.added_rlib {
+                writeln!(w, "{}", krate)?;
+            }
+        }
+        if !summary.skipped.is_empty() {
+            write!(w, "\nSkipped crates:\n\n")?;
+            for (krate, reason) in summary.skipped {
+                writeln!(w, "{}: {}", krate, reason)?;
+            }
+        }
+        if !summary.common_errors.is_empty() {
+            write!(w, "\nMost common errors:\n\n")?;
+            for (msg, count) in summary.common_errors {
+                writeln!(w, "{} crates: {}", count, msg)?;
+            }
+        }
+        write!(w, "\n{}", summary.timing)?;
+        w.flush()
+    }
+}
+
+/// Markdown output with a collapsible section for each crate.
+struct MarkdownWriter<W>(W);
+impl<W: Write> ReportWriter for MarkdownWriter<W> {
+    fn write_crate(&mut self, krate: &CrateReport) -> io::Result<()> {
+        let w = &mut self.0;
+        write!(w, "<details>\n<summary><code>{}</code>: ", krate.name)?;
+        let mut sep = "";
+        if krate.ice_msg.is_some() {
+            write!(w, "ICE")?;
+            sep = ", ";
+        }
+        if krate.fix_msg.is_some() {
+            write!(w, "{}fix failed", sep)?;
+            sep = ", ";
+        }
+        if krate.err_msg.is_some() {
+            write!(w, "{}build failed", sep)?;
+            sep = ", ";
+        }
+        if let Some(dep) = krate.ambiguous_dep {
+            write!(w, "{}ambiguous dependency <code>{}</code>", sep, dep)?;
+            sep = ", ";
+        }
+        if let Some(original) = krate.duplicate_of {
+            write!(w, "{}same source as <code>{}</code>", sep, original)?;
+            sep = ", ";
+        }
+        if !krate.lint_msgs.is_empty() {
+            write!(w, "{}{} warnings", sep, krate.lint_msgs.len())?;
+            sep = ", ";
+        }
+        if let Some(diff) = krate.diff {
+            write!(
+                w,
+                "{}{} new, {} fixed warnings",
+                sep,
+                diff.added.len(),
+                diff.removed.len()
+            )?;
+            if diff.new_ice {
+                write!(w, ", new ICE")?;
+            }
+            if diff.fixed_ice {
+                write!(w, ", fixed ICE")?;
+            }
+        }
+        if let Some(downloads) = krate.downloads {
+            write!(w, " ({} downloads)", downloads)?;
+        }
+        if krate.prerelease_only {
+            write!(w, " (prerelease only)")?;
+        }
+        write!(w, "</summary>\n\n")?;
+
+        if let Some(msg) = krate.fix_msg {
+            write!(w, "#### Failed to apply fixes\n\n```text\n{}\n```\n\n", msg)?;
+        }
+        for group in krate.lint_groups() {
+            write!(
+                w,
+                "#### `{}`: {} occurrences\n\n```text\n",
+                group[0].0,
+                group.len()
+            )?;
+            for (_, m) in group {
+                w.write_all(m.as_bytes())?;
+            }
+            write!(w, "```\n\n")?;
+        }
+        if let Some(msg) = krate.err_msg {
+            write!(w, "#### Build failed\n\n```text\n{}\n```\n\n", msg)?;
+        }
+        if let Some(msg) = krate.ice_msg {
+            write!(w, "#### ICE\n\n```text\n{}\n```\n\n", msg)?;
+        }
+        if let Some(repro) = krate.ice_repro {
+            write!(w, "#### Reproduce\n\n```sh\n{}```\n\n", repro)?;
+        }
+        if let Some(diff) = krate.diff {
+            for (kind, msgs) in [("New", &diff.added), ("Fixed", &diff.removed)] {
+                if !msgs.is_empty() {
+                    write!(w, "#### {} warnings\n\n```text\n", kind)?;
+                    for (_, m) in msgs {
+                        w.write_all(m.as_bytes())?;
+                    }
+                    write!(w, "```\n\n")?;
+                }
+            }
+        }
+        write!(w, "</details>\n\n")?;
+        w.flush()
+    }
+
+    fn write_summary(&mut self, summary: &Summary) -> io::Result<()> {
+        let w = &mut self.0;
+        write!(w, "## Summary\n\n")?;
+        if let Some(toolchain) = summary.toolchain {
+            write!(w, "Toolchain: `{}`\n\n", toolchain)?;
+        }
+        if let Some(version) = summary.clippy_version {
+            write!(w, "Clippy: `{}`\n\n", version)?;
+        }
+        if !summary.crates.is_empty() {
+            write!(w, "| Crate | Status | Downloads |\n| --- | --- | --- |\n")?;
+            for (krate, status, downloads) in summary.crates {
+                write!(w, "| `{}` | {} | ", krate, status)?;
+                if let Some(downloads) = downloads {
+                    write!(w, "{}", downloads)?;
+                }
+                writeln!(w, " |")?;
+            }
+            writeln!(w)?;
+        }
+        write!(
+            w,
+            "| Lint | Occurrences | Default level | Group |\n| --- | --- | --- | --- |\n"
+        )?;
+        for (lint, count) in summary.lints {
+            let info = summary.lint_info.get(lint);
+            writeln!(
+                w,
+                "| `{}` | {} | {} | {} |",
+                lint,
+                count,
+                info.map_or("", |info| info.default_level.as_str()),
+                info.and_then(|info| info.group.as_deref()).unwrap_or(""),
+            )?;
+        }
+        let unfired = summary.unfired_lints();
+        if !unfired.is_empty() {
+            write!(w, "\n### Lints with no occurrences\n\n")?;
+            for lint in unfired {
+                writeln!(w, "* `{}`", lint)?;
+            }
+        }
+        let histograms = summary.sorted_histograms();
+        if !histograms.is_empty() {
+            write!(w, "\n### Crates by occurrences per crate\n\n| Lint |")?;
+            let labels: Vec<_> = histograms[0].1.buckets().map(|(label, _)| label).collect();
+            for label in &labels {
+                write!(w, " {} |", label)?;
+            }
+            write!(w, "\n| --- |{}\n", " --- |".repeat(labels.len()))?;
+            for (lint, histogram) in histograms {
+                write!(w, "| `{}` |", lint)?;
+                for (_, count) in histogram.buckets() {
+                    write!(w, " {} |", count)?;
+                }
+                writeln!(w)?;
+            }
+        }
+        if let Some((lints, total)) = summary.sorted_churn() {
+            write!(
+                w,
+                "\n### Lines changed by fixes\n\n| Lint | Added | Removed |\n| --- | --- | --- |\n"
+            )?;
+            for (lint, churn) in lints {
+                writeln!(w, "| `{}` | {} | {} |", lint, churn.added, churn.removed)?;
+            }
+            writeln!(w, "| **Total** | {} | {} |", total.added, total.removed)?;
+        }
+        if !summary.version_diffs.is_empty() {
+            write!(
+                w,
+                "\n### Lints which changed between versions\n\n\
+                | Crate | Lint | Occurrences by version |\n| --- | --- | --- |\n"
+            )?;
+            for diff in summary.version_diffs {
+                write!(w, "| `{}` | `{}` |", diff.krate, diff.lint)?;
+                for (i, (version, count)) in diff.counts.iter().enumerate() {
+                    write!(
+                        w,
+                        "{} {}: {}",
+                        if i == 0 { "" } else { "," },
+                        version,
+                        count
+                    )?;
+                }
+                writeln!(w, " |")?;
+            }
+        }
+        if let Some(totals) = summary.diff_totals {
+            write!(
+                w,
+                "\n### Differences from the compared clippy\n\n\
+                | Lint | New | Fixed |\n| --- | --- | --- |\n"
+            )?;
+            for (lint, added, removed) in totals.sorted_lints() {
+                writeln!(w, "| `{}` | {} | {} |", lint, added, removed)?;
+            }
+            if !totals.new_ices.is_empty() || !totals.fixed_ices.is_empty() {
+                writeln!(w)?;
+            }
+            for krate in &totals.new_ices {
+                writeln!(w, "* `{}`: new ICE", krate)?;
+            }
+            for krate in &totals.fixed_ices {
+                writeln!(w, "* `{}`: fixed ICE", krate)?;
+            }
+        }
+        if !summary.omitted.is_empty() {
+            write!(w, "\n### Messages left out of the report\n\n")?;
+            for (lint, count) in summary.sorted_omitted() {
+                writeln!(w, "* `{}`: … and {} more", lint, count)?;
+            }
+        }
+        if !summary.suggestion_samples.is_empty() {
+            write!(w, "\n### Suggested replacements\n")?;
+            for (lint, samples) in summary.sorted_suggestion_samples() {
+                write!(w, "\n`{}`:\n\n", lint)?;
+                for sample in &samples.samples {
+                    writeln!(w, "* ``{:?}``", sample)?;
+                }
+                if samples.truncated {
+                    writeln!(w, "* …")?;
+                }
+            }
+        }
+        if !summary.added_rlib.is_empty() {
+            write!(
+                w,
+                "\n<details>\n<summary>{} crates checked with <code>rlib</code> added to their \
+                crate types</summary>\n\n",
+                summary.added_rlib.len()
+            )?;
+            for krate in summary.added_rlib {
+                writeln!(w, "* `{}`", krate)?;
+            }
+            write!(w, "\n</details>\n")?;
+        }
+        if !summary.skipped.is_empty() {
+            write!(
+                w,
+                "\n<details>\n<summary>{} skipped crates</summary>\n\n",
+                summary.skipped.len()
+            )?;
+            for (krate, reason) in summary.skipped {
+                writeln!(w, "* `{}`: {}", krate, reason)?;
+            }
+            write!(w, "\n</details>\n")?;
+        }
+        if !summary.common_errors.is_empty() {
+            write!(
+                w,
+                "\n### Most common errors\n\n| Crates | Error |\n| --- | --- |\n"
+            )?;
+            for (msg, count) in summary.common_errors {
+                writeln!(w, "| {} | `{}` |", count, msg.replace('|', "\\|"))?;
+            }
+        }
+        write!(w, "\n```text\n{}```\n", summary.timing)?;
+        w.flush()
+    }
+}
+
+/// A single JSON document written once the run is complete.
+struct JsonWriter<W> {
+    w: W,
+    crates: Vec<Value>,
+}
+impl<W: Write> ReportWriter for JsonWriter<W> {
+    fn write_crate(&mut self, krate: &CrateReport) -> io::Result<()> {
+        self.crates.push(krate.to_json());
+        Ok(())
+    }
+
+    fn write_summary(&mut self, summary: &Summary) -> io::Result<()> {
+        let report = json!({
+            "crates": self.crates,
+            "summary": summary.to_json(),
+        });
+        serde_json::to_writer_pretty(&mut self.w, &report)?;
+        writeln!(self.w)?;
+        self.w.flush()
+    }
+}
+
+/// A JSON object for each crate, followed by one for the summary.
+struct NdjsonWriter<W>(W);
+impl<W: Write> ReportWriter for NdjsonWriter<W> {
+    fn write_crate(&mut self, krate: &CrateReport) -> io::Result<()> {
+        let mut value = krate.to_json();
+        value["type"] = "crate".into();
+        serde_json::to_writer(&mut self.0, &value)?;
+        writeln!(self.0)?;
+        self.0.flush()
+    }
+
+    fn write_summary(&mut self, summary: &Summary) -> io::Result<()> {
+        let mut value = summary.to_json();
+        value["type"] = "summary".into();
+        serde_json::to_writer(&mut self.0, &value)?;
+        writeln!(self.0)?;
+        self.0.flush()
+    }
+}
@@ -0,0 +1,170 @@
+//! SARIF output for editors and CI code scanning.
+
+use crate::lint_info::LintInfo;
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use std::{collections::HashMap, fs, io, path::Path};
+
+/// Where a lint was emitted, taken from the diagnostic's primary span.
+pub struct LintSpan {
+    pub lint: String,
+    /// The diagnostic's message without the rendered source snippet.
+    pub message: String,
+    /// Whether the lint was emitted as an error.
+    pub denied: bool,
+    /// The file path relative to the crate root. Files outside the crate are absolute paths.
+    pub file: String,
+    pub line_start: usize,
+    pub column_start: usize,
+    pub line_end: usize,
+    pub column_end: usize,
+}
+
+/// Writes a SARIF 2.1.0 log with a rule for each tested lint and a result for each occurrence.
+/// `spans` holds the crate each span was found in.
+pub fn write(
+    path: &Path,
+    lints: &HashMap<String, usize>,
+    lint_info: &HashMap<String, LintInfo>,
+    spans: &[(String, LintSpan)],
+) -> Result<()> {
+    let file = fs::File::create(path)
+        .with_context(|| format!("error creating file `{}`", path.display()))?;
+    serde_json::to_writer_pretty(io::BufWriter::new(file), &to_json(lints, lint_info, spans))
+        .with_context(|| format!("error writing file `{}`", path.display()))
+}
+
+fn to_json(
+    lints: &HashMap<String, usize>,
+    lint_info: &HashMap<String, LintInfo>,
+    spans: &[(String, LintSpan)],
+) -> Value {
+    let mut rules: Vec<_> = lints.keys().map(String::as_str).collect();
+    rules.sort_unstable();
+    let rule_index: HashMap<_, _> = rules.iter().enumerate().map(|(i, &r)| (r, i)).collect();
+    let rules: Vec<_> = rules
+        .into_iter()
+        .map(|lint| rule(lint, lint_info.get(lint)))
+        .collect();
+    let results: Vec<_> = spans
+        .iter()
+        .map(|(krate, span)| result(krate, span, rule_index.get(span.lint.as_str()).copied()))
+        .collect();
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "clippy",
+                    "informationUri": "https://github.com/rust-lang/rust-clippy",
+                    "rules": rules,
+                },
+            },
+            "results": results,
+        }],
+    })
+}
+
+fn rule(lint: &str, info: Option<&LintInfo>) -> Value {
+    let mut rule = json!({ "id": lint });
+    if let Some(info) = info.filter(|info| !info.description.is_empty()) {
+        rule["shortDescription"] = json!({ "text": info.description });
+    }
+    if let Some(name) = lint.strip_prefix("clippy::") {
+        rule["helpUri"] = format!(
+            "https://rust-lang.github.io/rust-clippy/master/index.html#{}",
+            name
+        )
+        .into();
+    }
+    rule
+}
+
+fn result(krate: &str, span: &LintSpan, rule_index: Option<usize>) -> Value {
+    let uri = if Path::new(&span.file).is_absolute() {
+        span.file.replace('\\', "/")
+    } else {
+        format!("{}/{}", krate, span.file.replace('\\', "/"))
+    };
+    let mut result = json!({
+        "ruleId": span.lint,
+        "level": if span.denied { "error" } else { "warning" },
+        "message": { "text": span.message },
+        "locations": [{
+            "physicalLocation": {
+                "artifactLocation": { "uri": uri },
+                "region": {
+                    "startLine": span.line_start,
+                    "startColumn": span.column_start,
+                    "endLine": span.line_end,
+                    "endColumn": span.column_end,
+                },
+            },
+        }],
+    });
+    if let Some(i) = rule_index {
+        result["ruleIndex"] = i.into();
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::{to_json, LintSpan};
+    use crate::lint_info::LintInfo;
+    use std::collections::HashMap;
+
+    #[test]
+    fn sarif_log() {
+        let lints = HashMap::from([
+            ("clippy::needless_return".to_owned(), 1),
+            ("clippy::let_unit_value".to_owned(), 0),
+        ]);
+        let lint_info = HashMap::from([(
+            "clippy::needless_return".to_owned(),
+            LintInfo {
+                default_level: "warn".into(),
+                group: Some("style".into()),
+                description:
+                    "using a return statement like `return expr;` where an expression would suffice"
+                        .into(),
+            },
+        )]);
+        let spans = [(
+            "foo-1.0.0".to_owned(),
+            LintSpan {
+                lint: "clippy::needless_return".into(),
+                message: "unneeded `return` statement".into(),
+                denied: false,
+                file: "src/lib.rs".into(),
+                line_start: 3,
+                column_start: 5,
+                line_end: 3,
+                column_end: 14,
+            },
+        )];
+        let log = to_json(&lints, &lint_info, &spans);
+        assert_eq!(log["version"], "2.1.0");
+        let run = &log["runs"][0];
+        let rules = run["tool"]["driver"]["rules"].as_array().unwrap();
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0]["id"], "clippy::let_unit_value");
+        assert!(rules[0].get("shortDescription").is_none());
+        assert_eq!(rules[1]["id"], "clippy::needless_return");
+        assert_eq!(
+            rules[1]["helpUri"],
+            "https://rust-lang.github.io/rust-clippy/master/index.html#needless_return"
+        );
+
+        let result = &run["results"][0];
+        assert_eq!(result["ruleId"], "clippy::needless_return");
+        assert_eq!(result["ruleIndex"], 1);
+        assert_eq!(result["level"], "warning");
+        assert_eq!(result["message"]["text"], "unneeded `return` statement");
+        let location = &result["locations"][0]["physicalLocation"];
+        assert_eq!(location["artifactLocation"]["uri"], "foo-1.0.0/src/lib.rs");
+        assert_eq!(location["region"]["startLine"], 3);
+        assert_eq!(location["region"]["endColumn"], 14);
+    }
+}
@@ -0,0 +1,114 @@
+//! Comparison of lint counts between versions of the same crate.
+
+use clippy_lint_test::CrateId;
+use std::{
+    cmp::Ordering,
+    collections::{BTreeSet, HashMap},
+};
+
+/// A lint which fired a different number of times on different versions of the same crate.
+pub struct VersionDiff {
+    pub krate: String,
+    pub lint: String,
+    /// Each checked version and the number of times the lint fired on it, oldest first.
+    pub counts: Vec<(String, usize)>,
+}
+
+/// Finds all lints whose count differs between the versions of a crate. `crate_lints` contains the
+/// number of times each lint fired on each crate ID. Crates which didn't build successfully should
+/// be excluded. The result is sorted by crate name, then lint name.
+pub fn version_diffs(crate_lints: &HashMap<String, HashMap<String, usize>>) -> Vec<VersionDiff> {
+    let mut by_name = HashMap::<&str, Vec<_>>::new();
+    for (krate, lints) in crate_lints {
+        if let Some(id) = CrateId::parse(krate) {
+            by_name.entry(id.name).or_default().push((id, lints));
+        }
+    }
+
+    let mut res = Vec::new();
+    for (name, mut versions) in by_name {
+        if versions.len() < 2 {
+            continue;
+        }
+        versions.sort_by(|(x, _), (y, _)| {
+            x.version
+                .main()
+                .cmp(&y.version.main())
+                // A pre-release comes before its release.
+                .then_with(|| match (x.version.pre(), y.version.pre()) {
+                    (Some(x), Some(y)) => x.stream.cmp(y.stream).then(x.version.cmp(&y.version)),
+                    (Some(_), None) => Ordering::Less,
+                    (None, Some(_)) => Ordering::Greater,
+                    (None, None) => Ordering::Equal,
+                })
+                .then_with(|| x.version.to_string().cmp(&y.version.to_string()))
+        });
+        let lints: BTreeSet<_> = versions
+            .iter()
+            .flat_map(|(_, lints)| lints.keys())
+            .collect();
+        for lint in lints {
+            let counts: Vec<_> = versions
+                .iter()
+                .map(|(id, lints)| {
+                    (
+                        id.version.to_string(),
+                        lints.get(lint).copied().unwrap_or(0),
+                    )
+                })
+                .collect();
+            if counts.iter().any(|&(_, count)| count != counts[0].1) {
+                res.push(VersionDiff {
+                    krate: name.into(),
+                    lint: lint.clone(),
+                    counts,
+                });
+            }
+        }
+    }
+    res.sort_by(|x, y| x.krate.cmp(&y.krate).then_with(|| x.lint.cmp(&y.lint)));
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use super::version_diffs;
+    use std::collections::HashMap;
+
+    #[test]
+    fn diff_versions() {
+        let lints = |lints: &[(&str, usize)]| {
+            lints
+                .iter()
+                .map(|&(lint, count)| (lint.to_owned(), count))
+                .collect::<HashMap<_, _>>()
+        };
+        let crate_lints = HashMap::from([
+            (
+                "foo-0.10.0".to_owned(),
+                lints(&[("clippy::bar", 1), ("clippy::baz", 2)]),
+            ),
+            (
+                "foo-0.9.0".to_owned(),
+                lints(&[("clippy::bar", 3), ("clippy::baz", 2)]),
+            ),
+            ("foo-1.0.0".to_owned(), lints(&[("clippy::baz", 2)])),
+            ("foo-1.0.0-alpha.1".to_owned(), lints(&[("clippy::baz", 2)])),
+            ("single-1.0.0".to_owned(), lints(&[("clippy::bar", 1)])),
+        ]);
+
+        let res = version_diffs(&crate_lints);
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].krate, "foo");
+        assert_eq!(res[0].lint, "clippy::bar");
+        assert_eq!(
+            res[0].counts,
+            [
+                ("0.9.0".to_owned(), 3),
+                ("0.10.0".to_owned(), 1),
+                ("1.0.0-alpha.1".to_owned(), 0),
+                ("1.0.0".to_owned(), 0),
+            ]
+        );
+    }
+}